@@ -2,10 +2,19 @@
 #import bevy_pbr::mesh_bindings
 #import bevy_pbr::mesh_functions
 
+#ifdef VOXEL_PANEL_NORMAL_MAP
 @group(1) @binding(1)
-var normal_map_texture: texture_2d<f32>;
+var panel_normal_map: texture_2d<f32>;
 @group(1) @binding(2)
-var normal_map_sampler: sampler;
+var panel_normal_sampler: sampler;
+#endif
+
+#ifdef VOXEL_BEVEL_NORMAL_MAP
+@group(1) @binding(4)
+var bevel_normal_map: texture_2d<f32>;
+@group(1) @binding(5)
+var bevel_normal_sampler: sampler;
+#endif
 
 #import bevy_pbr::pbr_types
 #import bevy_pbr::utils
@@ -25,14 +34,50 @@ struct FragmentInput {
     @location(4) roughness: f32,
     @location(5) reflectance: f32,
     @location(6) emission: vec4<f32>,
+    @location(7) ao: f32,
+    @location(8) world_tangent: vec3<f32>,
+    @location(9) world_bitangent: vec3<f32>,
+    @location(10) uv: vec2<f32>,
+    @location(11) @interpolate(flat) pattern: u32,
 };
 
+// Perturbs the normal by the normal map of the voxel's detail pattern, if it has one. Maps are
+// sampled outside of the branches, as sampling needs uniform control flow.
+fn detail_normal(
+    pattern: u32,
+    uv: vec2<f32>,
+    N: vec3<f32>,
+    T: vec3<f32>,
+    B: vec3<f32>,
+) -> vec3<f32> {
+    var Nt = vec3<f32>(0.0, 0.0, 1.0);
+
+#ifdef VOXEL_PANEL_NORMAL_MAP
+    let panel = textureSample(panel_normal_map, panel_normal_sampler, uv).rgb * 2.0 - 1.0;
+    if (pattern == 1u) {
+        Nt = panel;
+    }
+#endif
+
+#ifdef VOXEL_BEVEL_NORMAL_MAP
+    let bevel = textureSample(bevel_normal_map, bevel_normal_sampler, uv).rgb * 2.0 - 1.0;
+    if (pattern == 2u) {
+        Nt = bevel;
+    }
+#endif
+
+    return normalize(Nt.x * T + Nt.y * B + Nt.z * N);
+}
+
 @fragment
 fn fragment(in: FragmentInput) -> @location(0) vec4<f32> {
     var pbr_input: PbrInput = pbr_input_new();
 
     pbr_input.frag_coord = in.frag_coord;
-    pbr_input.material.base_color = in.color;
+    pbr_input.material.base_color = vec4(in.color.rgb * in.ao, in.color.a);
+#ifdef VOXEL_TRANSLUCENT
+    pbr_input.material.flags = STANDARD_MATERIAL_FLAGS_ALPHA_MODE_BLEND;
+#endif
     pbr_input.material.metallic = in.metallic;
     pbr_input.material.perceptual_roughness = in.roughness;
     pbr_input.material.reflectance = in.reflectance;
@@ -41,9 +86,12 @@ fn fragment(in: FragmentInput) -> @location(0) vec4<f32> {
     pbr_input.world_position = in.world_position;
     pbr_input.is_orthographic = view.projection[3].w == 1.0;
     pbr_input.world_normal = in.world_normal;
-    pbr_input.N = apply_normal_mapping(
-        pbr_input.material.flags,
-        pbr_input.world_normal
+    pbr_input.N = detail_normal(
+        in.pattern,
+        in.uv,
+        in.world_normal,
+        in.world_tangent,
+        in.world_bitangent
     );
     pbr_input.V = calculate_view(in.world_position, pbr_input.is_orthographic);
 
@@ -0,0 +1,30 @@
+#import bevy_pbr::mesh_view_types
+#import bevy_pbr::mesh_types
+
+@group(0) @binding(0)
+var<uniform> view: View;
+
+@group(1) @binding(0)
+var<uniform> mesh: Mesh;
+
+#import bevy_pbr::mesh_functions
+
+// Only the position of a blocky vertex is needed, the rest of the packed data is ignored. See
+// voxel_vert.wgsl for the layout.
+struct Vertex {
+    @location(0) packed: vec2<u32>,
+};
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+};
+
+@vertex
+fn vertex(vertex: Vertex) -> VertexOutput {
+    let bits = vec3<u32>(vertex.packed.x, vertex.packed.x >> 7u, vertex.packed.x >> 14u);
+    let position = vec3<f32>(bits & vec3<u32>(127u)) - vec3<f32>(32.0);
+
+    var out: VertexOutput;
+    out.clip_position = mesh_position_local_to_clip(mesh.model, vec4<f32>(position, 1.0));
+    return out;
+}
@@ -2,10 +2,31 @@
 #import bevy_pbr::mesh_bindings
 #import bevy_pbr::mesh_functions
 
+struct VoxelMaterial {
+    ao_curve: vec4<f32>,
+};
+
+@group(1) @binding(0)
+var<uniform> material: VoxelMaterial;
+
+// Two texels per material: color and opacity, then metallic, roughness, reflectance and emission.
+@group(1) @binding(3)
+var material_table: texture_2d<f32>;
+
 struct Vertex {
+#ifdef VOXEL_SMOOTH
     @location(0) position: vec3<f32>,
     @location(1) pbr_norm: vec4<u32>,
     @location(2) color: vec4<f32>,
+    @location(3) ao: u32,
+    @location(4) opacity: f32,
+    @location(5) normal: vec3<f32>,
+#else
+    // Position (7 bits per axis from bit 0, offset by 32), face index (bits 21-23), AO level
+    // (bits 24-25), face corner (bits 26-27) and detail pattern (bits 28-29), then the material
+    // index.
+    @location(0) packed: vec2<u32>,
+#endif
 };
 
 struct VertexOutput {
@@ -17,6 +38,11 @@ struct VertexOutput {
     @location(4) roughness: f32,
     @location(5) reflectance: f32,
     @location(6) emission: vec4<f32>,
+    @location(7) ao: f32,
+    @location(8) world_tangent: vec3<f32>,
+    @location(9) world_bitangent: vec3<f32>,
+    @location(10) uv: vec2<f32>,
+    @location(11) @interpolate(flat) pattern: u32,
 };
 
 var<private> NORMALS: array<vec3<f32>, 6> = array<vec3<f32>, 6>(
@@ -28,22 +54,80 @@ var<private> NORMALS: array<vec3<f32>, 6> = array<vec3<f32>, 6>(
     vec3<f32>(0., -1., 0.),
 );
 
+var<private> TANGENTS: array<vec3<f32>, 6> = array<vec3<f32>, 6>(
+    vec3<f32>(0., 0., 1.),
+    vec3<f32>(1., 0., 0.),
+    vec3<f32>(0., 0., -1.),
+    vec3<f32>(-1., 0., 0.),
+    vec3<f32>(0., 0., 1.),
+    vec3<f32>(1., 0., 0.),
+);
+
+var<private> BITANGENTS: array<vec3<f32>, 6> = array<vec3<f32>, 6>(
+    vec3<f32>(0., 1., 0.),
+    vec3<f32>(0., 1., 0.),
+    vec3<f32>(0., 1., 0.),
+    vec3<f32>(0., 1., 0.),
+    vec3<f32>(1., 0., 0.),
+    vec3<f32>(0., 0., 1.),
+);
+
+// Texel `offset` of material `index`. Rows are `MATERIAL_TABLE_WIDTH` (512) texels wide.
+fn material_texel(index: u32, offset: u32) -> vec4<f32> {
+    let texel = index * 2u + offset;
+    return textureLoad(material_table, vec2<i32>(i32(texel % 512u), i32(texel / 512u)), 0);
+}
+
 @vertex
 fn vertex(vertex: Vertex) -> VertexOutput {
     var out: VertexOutput;
 
-    out.world_position = mesh_position_local_to_world(mesh.model, vec4<f32>(vertex.position.xyz, 1.0));
+#ifdef VOXEL_SMOOTH
+    let position = vertex.position;
+#else
+    let bits = vec3<u32>(vertex.packed.x, vertex.packed.x >> 7u, vertex.packed.x >> 14u);
+    let position = vec3<f32>(bits & vec3<u32>(127u)) - vec3<f32>(32.0);
+#endif
+
+    out.world_position = mesh_position_local_to_world(mesh.model, vec4<f32>(position, 1.0));
     out.clip_position = mesh_position_world_to_clip(out.world_position);
 
-    var normal = NORMALS[vertex.pbr_norm.w];
+#ifdef VOXEL_SMOOTH
+    var normal = vertex.normal;
+    var color = vec4(vertex.color.rgb, vertex.opacity);
+    var pbr = vec4<f32>(vertex.pbr_norm) / 255.0;
+    pbr.w = vertex.color.a;
+    var ao = vertex.ao;
+
+    // Smooth surfaces have no faces to put detail on.
+    out.world_tangent = vec3<f32>(0.0);
+    out.world_bitangent = vec3<f32>(0.0);
+    out.uv = vec2<f32>(0.0);
+    out.pattern = 0u;
+#else
+    let face = (vertex.packed.x >> 21u) & 7u;
+    var normal = NORMALS[face];
+    var ao = (vertex.packed.x >> 24u) & 3u;
+    var color = material_texel(vertex.packed.y, 0u);
+    var pbr = material_texel(vertex.packed.y, 1u);
+
+    // Corners go counter-clockwise from the face's origin, along its tangent then bitangent. The
+    // texture's up is the bitangent, so V is flipped.
+    let corner = (vertex.packed.x >> 26u) & 3u;
+    out.uv = vec2<f32>(f32(corner == 1u || corner == 2u), f32(corner < 2u));
+    out.world_tangent = normalize((mesh.model * vec4<f32>(TANGENTS[face], 0.0)).xyz);
+    out.world_bitangent = normalize((mesh.model * vec4<f32>(BITANGENTS[face], 0.0)).xyz);
+    out.pattern = (vertex.packed.x >> 28u) & 3u;
+#endif
     out.world_normal = mesh_normal_local_to_world(normal);
-    out.color = vec4(vertex.color.rgb, 1.0);
-    out.metallic = f32(vertex.pbr_norm.x) / 255.0;
-    out.roughness = f32(vertex.pbr_norm.y) / 255.0;
-    out.reflectance = f32(vertex.pbr_norm.z) / 255.0;
+    out.color = color;
+    out.metallic = pbr.x;
+    out.roughness = pbr.y;
+    out.reflectance = pbr.z;
 
-    var emission = vertex.color.a * 24.0;
-    out.emission = vec4(vertex.color.rgb * emission, clamp(vertex.color.a, 0.0, 1.0));
+    var emission = pbr.w * 24.0;
+    out.emission = vec4(color.rgb * emission, clamp(pbr.w, 0.0, 1.0));
+    out.ao = material.ao_curve[min(ao, 3u)];
 
     return out;
 }
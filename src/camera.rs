@@ -3,6 +3,7 @@ use bevy::{
     prelude::*,
 };
 use egui::style::Margin;
+use serde::{Deserialize, Serialize};
 
 pub struct CameraPlugin;
 
@@ -24,6 +25,36 @@ pub struct CameraController {
     pub margins: Margin,
 }
 
+/// The parts of a `CameraController` kept between sessions.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct CameraParams {
+    pub mouse_sensitive: f32,
+    pub scroll_sensitive: f32,
+    pub azimuth: f32,
+    pub zenith: f32,
+    pub distance: f32,
+}
+
+impl CameraController {
+    pub fn params(&self) -> CameraParams {
+        CameraParams {
+            mouse_sensitive: self.mouse_sensitive,
+            scroll_sensitive: self.scroll_sensitive,
+            azimuth: self.azimuth,
+            zenith: self.zenith,
+            distance: self.distance,
+        }
+    }
+
+    pub fn set_params(&mut self, params: CameraParams) {
+        self.mouse_sensitive = params.mouse_sensitive;
+        self.scroll_sensitive = params.scroll_sensitive;
+        self.azimuth = params.azimuth;
+        self.zenith = params.zenith;
+        self.distance = params.distance;
+    }
+}
+
 pub enum CameraTarget {
     Entity(Entity),
     Point(Vec3),
@@ -0,0 +1,532 @@
+use bevy::{ecs::system::SystemParam, prelude::*};
+
+use crate::voxel::{
+    Buffer, Mesher, PbrProps, VoxelChunks, VoxelColliderMode, VoxelColliders, VoxelDetailTextures,
+    VoxelLights, VoxelMaterial, WorldCoord,
+};
+
+use super::{entity_buffer::EntityBuffer, EditorResource};
+
+/// A change to the scene being edited, holding the state from both before and after it so it can
+/// be undone and redone.
+#[derive(Clone)]
+pub enum EditorCommand {
+    /// The committed voxels of an entity were replaced.
+    Voxels {
+        entity: Entity,
+        before: Buffer,
+        after: Buffer,
+    },
+    /// The snapshot was spawned as a child of `parent`.
+    Spawn {
+        parent: Entity,
+        snapshot: EntitySnapshot,
+    },
+    /// The snapshot was despawned from under `parent`.
+    Despawn {
+        parent: Entity,
+        snapshot: EntitySnapshot,
+    },
+    Transform {
+        entity: Entity,
+        before: Transform,
+        after: Transform,
+    },
+    Parent {
+        entity: Entity,
+        before: Entity,
+        after: Entity,
+    },
+    Rename {
+        entity: Entity,
+        before: String,
+        after: String,
+    },
+    /// The entity being edited changed.
+    Activate { before: Entity, after: Entity },
+}
+
+impl EditorCommand {
+    /// The command that undoes this one.
+    pub fn inverse(&self) -> Self {
+        match self.clone() {
+            Self::Voxels {
+                entity,
+                before,
+                after,
+            } => Self::Voxels {
+                entity,
+                before: after,
+                after: before,
+            },
+            Self::Spawn { parent, snapshot } => Self::Despawn { parent, snapshot },
+            Self::Despawn { parent, snapshot } => Self::Spawn { parent, snapshot },
+            Self::Transform {
+                entity,
+                before,
+                after,
+            } => Self::Transform {
+                entity,
+                before: after,
+                after: before,
+            },
+            Self::Parent {
+                entity,
+                before,
+                after,
+            } => Self::Parent {
+                entity,
+                before: after,
+                after: before,
+            },
+            Self::Rename {
+                entity,
+                before,
+                after,
+            } => Self::Rename {
+                entity,
+                before: after,
+                after: before,
+            },
+            Self::Activate { before, after } => Self::Activate {
+                before: after,
+                after: before,
+            },
+        }
+    }
+
+    /// Folds `next` into this command if both change the same thing on the same entity, so a drag
+    /// or a bit of typing is undone in one go.
+    pub fn merge(&mut self, next: &Self) -> bool {
+        match (self, next) {
+            (
+                Self::Transform { entity, after, .. },
+                Self::Transform {
+                    entity: next_entity,
+                    after: next_after,
+                    ..
+                },
+            ) if entity == next_entity => *after = *next_after,
+            (
+                Self::Rename { entity, after, .. },
+                Self::Rename {
+                    entity: next_entity,
+                    after: next_after,
+                    ..
+                },
+            ) if entity == next_entity => *after = next_after.clone(),
+            _ => return false,
+        }
+
+        true
+    }
+
+    /// Replaces references to an entity, as despawned entities come back with a new id.
+    pub fn remap(&mut self, from: Entity, to: Entity) {
+        let remap = |entity: &mut Entity| {
+            if *entity == from {
+                *entity = to;
+            }
+        };
+
+        match self {
+            Self::Voxels { entity, .. }
+            | Self::Transform { entity, .. }
+            | Self::Rename { entity, .. } => remap(entity),
+            Self::Spawn { parent, snapshot } | Self::Despawn { parent, snapshot } => {
+                remap(parent);
+                snapshot.remap(from, to);
+            }
+            Self::Parent {
+                entity,
+                before,
+                after,
+            } => {
+                remap(entity);
+                remap(before);
+                remap(after);
+            }
+            Self::Activate { before, after } => {
+                remap(before);
+                remap(after);
+            }
+        }
+    }
+
+    /// The voxel buffers the command holds on to.
+    pub fn buffers(&self) -> Vec<&Buffer> {
+        match self {
+            Self::Voxels { before, after, .. } => vec![before, after],
+            Self::Spawn { snapshot, .. } | Self::Despawn { snapshot, .. } => {
+                let mut buffers = vec![];
+                snapshot.collect_buffers(&mut buffers);
+                buffers
+            }
+            _ => vec![],
+        }
+    }
+}
+
+/// Everything needed to respawn an entity of the editor hierarchy, and its children.
+#[derive(Clone)]
+pub struct EntitySnapshot {
+    /// The id the entity had when the snapshot was taken.
+    pub entity: Entity,
+    pub name: String,
+    pub transform: Transform,
+    pub voxels: Option<VoxelSnapshot>,
+    pub children: Vec<EntitySnapshot>,
+}
+
+#[derive(Clone)]
+pub struct VoxelSnapshot {
+    pub buffer: Buffer,
+    pub mesher: Mesher,
+    pub collider_mode: VoxelColliderMode,
+}
+
+impl EntitySnapshot {
+    /// Whether the entity is this one or one of its descendants.
+    pub fn contains(&self, entity: Entity) -> bool {
+        self.entity == entity || self.children.iter().any(|child| child.contains(entity))
+    }
+
+    fn remap(&mut self, from: Entity, to: Entity) {
+        if self.entity == from {
+            self.entity = to;
+        }
+        for child in &mut self.children {
+            child.remap(from, to);
+        }
+    }
+
+    fn collect_buffers<'a>(&'a self, buffers: &mut Vec<&'a Buffer>) {
+        if let Some(voxels) = &self.voxels {
+            buffers.push(&voxels.buffer);
+        }
+        for child in &self.children {
+            child.collect_buffers(buffers);
+        }
+    }
+}
+
+/// An edit requested by the editor UI. Edits are applied and recorded in the history by
+/// `apply_editor_edits`, which fills in the state they replace.
+pub enum EditorEdit {
+    Rename {
+        entity: Entity,
+        name: String,
+        /// Folds the edit into the last one if it renamed the same entity.
+        merge: bool,
+    },
+    Move {
+        entity: Entity,
+        transform: Transform,
+        /// Folds the edit into the last one if it moved the same entity.
+        merge: bool,
+    },
+    Reparent {
+        entity: Entity,
+        parent: Entity,
+    },
+    /// Spawns a new voxel entity under the parent, starting out with a single voxel.
+    SpawnChild {
+        parent: Entity,
+        material: PbrProps,
+    },
+    Despawn {
+        entity: Entity,
+    },
+}
+
+/// The parts of the world that editor commands touch.
+#[derive(SystemParam)]
+pub struct EditorScene<'w, 's> {
+    commands: Commands<'w, 's>,
+    nodes: Query<
+        'w,
+        's,
+        (
+            &'static mut Name,
+            &'static mut Transform,
+            Option<&'static Parent>,
+            Option<&'static Children>,
+        ),
+    >,
+    voxels: Query<
+        'w,
+        's,
+        (
+            &'static mut EntityBuffer,
+            &'static VoxelChunks,
+            &'static VoxelColliders,
+        ),
+    >,
+    materials: ResMut<'w, Assets<VoxelMaterial>>,
+    images: ResMut<'w, Assets<Image>>,
+    detail_textures: Res<'w, VoxelDetailTextures>,
+}
+
+impl<'w, 's> EditorScene<'w, 's> {
+    fn name(&self, entity: Entity) -> String {
+        self.nodes
+            .get(entity)
+            .map(|(name, ..)| name.to_string())
+            .unwrap_or_default()
+    }
+
+    /// Snapshots the entity and its named descendants. Unnamed children (chunk meshes and
+    /// colliders) are rebuilt from the voxels instead.
+    fn capture(&self, entity: Entity) -> Option<EntitySnapshot> {
+        let (name, transform, _, children) = self.nodes.get(entity).ok()?;
+        let voxels =
+            self.voxels
+                .get(entity)
+                .ok()
+                .map(
+                    |(entity_buffer, voxel_chunks, voxel_colliders)| VoxelSnapshot {
+                        buffer: entity_buffer.commit_buffer.clone(),
+                        mesher: voxel_chunks.mesher,
+                        collider_mode: voxel_colliders.mode,
+                    },
+                );
+
+        Some(EntitySnapshot {
+            entity,
+            name: name.to_string(),
+            transform: *transform,
+            voxels,
+            children: children
+                .into_iter()
+                .flat_map(|children| children.iter())
+                .filter_map(|child| self.capture(*child))
+                .collect(),
+        })
+    }
+
+    /// Spawns the snapshot under `parent`, returning the ids of the old entities and their
+    /// replacements.
+    fn spawn(&mut self, parent: Entity, snapshot: &EntitySnapshot) -> Vec<(Entity, Entity)> {
+        let mut entity = self.commands.spawn((
+            Name::from(snapshot.name.clone()),
+            SpatialBundle::from_transform(snapshot.transform),
+        ));
+
+        if let Some(voxels) = &snapshot.voxels {
+            let mut voxel_chunks =
+                VoxelChunks::new(&mut self.materials, &mut self.images, &self.detail_textures);
+            voxel_chunks.set_mesher(voxels.mesher);
+            let mut voxel_colliders = VoxelColliders::default();
+            voxel_colliders.set_mode(voxels.collider_mode);
+
+            entity.insert((
+                EntityBuffer::new(voxels.buffer.clone()),
+                voxel_chunks,
+                VoxelLights::default(),
+                voxel_colliders,
+            ));
+        }
+
+        let id = entity.id();
+        self.commands.entity(parent).add_child(id);
+
+        let mut remapped = vec![(snapshot.entity, id)];
+        for child in &snapshot.children {
+            remapped.extend(self.spawn(id, child));
+        }
+        remapped
+    }
+
+    /// Applies the command, returning the ids of entities that were respawned with a new one.
+    fn apply(
+        &mut self,
+        voxel_editor: &mut EditorResource,
+        command: &EditorCommand,
+    ) -> Vec<(Entity, Entity)> {
+        match command {
+            EditorCommand::Voxels { entity, after, .. } => {
+                if let Ok((mut entity_buffer, ..)) = self.voxels.get_mut(*entity) {
+                    entity_buffer.restore(after.clone());
+                }
+            }
+            EditorCommand::Spawn { parent, snapshot } => return self.spawn(*parent, snapshot),
+            EditorCommand::Despawn { snapshot, .. } => {
+                self.commands.entity(snapshot.entity).despawn_recursive();
+            }
+            EditorCommand::Transform { entity, after, .. } => {
+                if let Ok((_, mut transform, ..)) = self.nodes.get_mut(*entity) {
+                    *transform = *after;
+                }
+            }
+            EditorCommand::Parent { entity, after, .. } => {
+                self.commands.entity(*entity).set_parent(*after);
+            }
+            EditorCommand::Rename { entity, after, .. } => {
+                if let Ok((mut name, ..)) = self.nodes.get_mut(*entity) {
+                    name.set(after.clone());
+                }
+            }
+            EditorCommand::Activate { after, .. } => {
+                voxel_editor.entity = *after;
+                voxel_editor.selection.clear();
+            }
+        }
+
+        vec![]
+    }
+
+    /// Turns an edit into the command that makes it, or `None` if the edit isn't possible.
+    fn record(
+        &self,
+        voxel_editor: &EditorResource,
+        edit: EditorEdit,
+    ) -> Option<(String, EditorCommand)> {
+        match edit {
+            EditorEdit::Rename { entity, name, .. } => {
+                let before = self.name(entity);
+                let label = format!("Rename {} to {}", before, name);
+                Some((
+                    label,
+                    EditorCommand::Rename {
+                        entity,
+                        before,
+                        after: name,
+                    },
+                ))
+            }
+            EditorEdit::Move {
+                entity, transform, ..
+            } => {
+                let (_, before, ..) = self.nodes.get(entity).ok()?;
+                Some((
+                    format!("Move {}", self.name(entity)),
+                    EditorCommand::Transform {
+                        entity,
+                        before: *before,
+                        after: transform,
+                    },
+                ))
+            }
+            EditorEdit::Reparent { entity, parent } => {
+                let (_, _, before, _) = self.nodes.get(entity).ok()?;
+                let before = before?.get();
+
+                // Entities can't be moved under themselves.
+                if before == parent || self.capture(entity)?.contains(parent) {
+                    return None;
+                }
+
+                Some((
+                    format!("Move {} under {}", self.name(entity), self.name(parent)),
+                    EditorCommand::Parent {
+                        entity,
+                        before,
+                        after: parent,
+                    },
+                ))
+            }
+            EditorEdit::SpawnChild { parent, material } => {
+                let mut buffer = Buffer::default();
+                buffer.set(WorldCoord(IVec3::ZERO), material);
+                buffer.dirty_chunks.clear();
+
+                let snapshot = EntitySnapshot {
+                    // Replaced by the id of the spawned entity once it's applied.
+                    entity: Entity::from_raw(u32::MAX),
+                    name: "New entity".into(),
+                    transform: default(),
+                    voxels: Some(VoxelSnapshot {
+                        buffer,
+                        mesher: default(),
+                        collider_mode: default(),
+                    }),
+                    children: vec![],
+                };
+
+                Some((
+                    format!("Create {} under {}", snapshot.name, self.name(parent)),
+                    EditorCommand::Spawn { parent, snapshot },
+                ))
+            }
+            EditorEdit::Despawn { entity } => {
+                let (_, _, parent, _) = self.nodes.get(entity).ok()?;
+                let parent = parent?.get();
+                let snapshot = self.capture(entity)?;
+
+                // The entity being edited has to stay around.
+                if snapshot.contains(voxel_editor.entity) || entity == voxel_editor.prefab_entity {
+                    return None;
+                }
+
+                Some((
+                    format!("Delete {}", snapshot.name),
+                    EditorCommand::Despawn { parent, snapshot },
+                ))
+            }
+        }
+    }
+}
+
+/// Applies the edits requested by the UI and records them in the history, then handles undo
+/// (Ctrl + Z), redo (Ctrl + Shift + Z or Ctrl + Y) and jumps from the history panel.
+pub fn apply_editor_edits(mut voxel_editor: ResMut<EditorResource>, mut scene: EditorScene) {
+    let voxel_editor = &mut *voxel_editor;
+
+    for edit in std::mem::take(&mut voxel_editor.edits) {
+        let merge = matches!(
+            edit,
+            EditorEdit::Rename { merge: true, .. } | EditorEdit::Move { merge: true, .. }
+        );
+        let (label, mut command) = match scene.record(voxel_editor, edit) {
+            Some(recorded) => recorded,
+            None => continue,
+        };
+        for (from, to) in scene.apply(voxel_editor, &command) {
+            command.remap(from, to);
+        }
+
+        if merge {
+            voxel_editor.history.push_merged(label, command);
+        } else {
+            voxel_editor.history.push(label, command);
+        }
+    }
+
+    let keyboard = &voxel_editor.constituents.keyboard;
+    let ctrl = keyboard.pressed(KeyCode::LControl);
+    let shift = keyboard.pressed(KeyCode::LShift);
+    let history = &voxel_editor.history;
+    let target = if let Some(target) = voxel_editor.history_jump.take() {
+        target
+    } else if ctrl && !shift && keyboard.just_pressed(KeyCode::Z) {
+        history.applied().saturating_sub(1)
+    } else if ctrl
+        && (shift && keyboard.just_pressed(KeyCode::Z) || keyboard.just_pressed(KeyCode::Y))
+    {
+        (history.applied() + 1).min(history.len())
+    } else {
+        return;
+    };
+
+    while voxel_editor.history.applied() != target {
+        let command = if target < voxel_editor.history.applied() {
+            voxel_editor.history.undo()
+        } else {
+            voxel_editor.history.redo()
+        };
+        let command = unwrap_or_return!(command);
+
+        for (from, to) in scene.apply(voxel_editor, &command) {
+            voxel_editor.history.remap(from, to);
+        }
+
+        // Spawned entities don't exist until commands are applied, and later commands may need
+        // them, so the rest of the jump waits for the next frame.
+        if matches!(command, EditorCommand::Spawn { .. }) {
+            if voxel_editor.history.applied() != target {
+                voxel_editor.history_jump = Some(target);
+            }
+            return;
+        }
+    }
+}
@@ -1,6 +1,7 @@
 use bevy::prelude::*;
+use bevy_egui::EguiContext;
 
-use crate::voxel::{raycast_buffer_voxels, VoxelRayHit, WorldCoord};
+use crate::voxel::{pick_voxel, raycast_buffer_voxels, VoxelPick, VoxelRayHit, WorldCoord};
 
 use super::{entity_buffer::EntityBuffer, EditorResource};
 
@@ -15,8 +16,11 @@ pub struct EditorConstituents {
     pub world_ray: Ray,
     pub local_ray: Ray,
     pub ray_hit: Option<VoxelRayHit>,
+    /// The nearest voxel under the cursor across all voxel entities, not just the focused one.
+    pub pick: Option<VoxelPick>,
     pub cursor: Vec2,
     pub drag_origin: Option<DragOrigin>,
+    /// Empty while egui wants keyboard input, see `editor_keyboard`.
     pub keyboard: Input<KeyCode>,
     pub mouse_buttons: Input<MouseButton>,
 }
@@ -30,30 +34,75 @@ pub struct DragOrigin {
     pub mouse_buttons: Input<MouseButton>,
 }
 
+/// The keyboard as the editor sees it: nothing is pressed while egui takes keyboard input, so typing
+/// into a text field doesn't trigger shortcuts.
+pub fn editor_keyboard(
+    keyboard: &Input<KeyCode>,
+    egui_context: &mut EguiContext,
+) -> Input<KeyCode> {
+    if egui_context.ctx_mut().wants_keyboard_input() {
+        default()
+    } else {
+        keyboard.clone()
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
 pub fn gather_editor_constituents(
     mut voxel_editor: ResMut<EditorResource>,
     camera: Query<(&GlobalTransform, &Camera)>,
     mouse_button_input: Res<Input<MouseButton>>,
     keycode_input: Res<Input<KeyCode>>,
+    mut egui_context: ResMut<EguiContext>,
     windows: Res<Windows>,
     global_transforms: Query<&GlobalTransform>,
-    entity_buffers: Query<&EntityBuffer>,
+    names: Query<&Name>,
+    entity_buffers: Query<(Entity, &GlobalTransform, &EntityBuffer)>,
 ) {
     // Gather constituents
     voxel_editor.constituents = {
         let window = unwrap_or_return!(windows.get_primary());
         let cursor = unwrap_or_return!(window.cursor_position());
-        let prefab_global_transform =
-            ok_or_return!(global_transforms.get(voxel_editor.prefab_entity));
-        let focus_global_transform = ok_or_return!(global_transforms.get(voxel_editor.entity));
         let (camera_global_transform, camera) = camera.single();
 
-        // Create a ray from the camera at the cursor position, in the global space of the focused
-        // entity.
+        // Create a ray from the camera at the cursor position, in world space.
         let world_ray = camera
             .viewport_to_world(camera_global_transform, cursor)
             .unwrap();
 
+        let pick = pick_voxel(
+            world_ray,
+            entity_buffers
+                .iter()
+                .map(|(entity, transform, entity_buffer)| {
+                    (entity, transform, &entity_buffer.commit_buffer)
+                }),
+        );
+
+        // The egui panels are drawn over the view, clicks on them aren't for the editor.
+        let pointer_on_ui = egui_context.ctx_mut().wants_pointer_input();
+        let left_clicked = !pointer_on_ui && mouse_button_input.just_pressed(MouseButton::Left);
+
+        // Alt + clicking a voxel of any entity samples its material, and clicking a voxel of
+        // another entity focuses that entity. Neither starts a drag.
+        let handled = match pick {
+            Some(pick) if left_clicked && keycode_input.pressed(KeyCode::LAlt) => {
+                let (_, _, entity_buffer) = entity_buffers.get(pick.entity).unwrap();
+                voxel_editor.material = entity_buffer.commit_buffer.get(pick.ray_hit.world_coord);
+                true
+            }
+            Some(pick) if left_clicked && pick.entity != voxel_editor.entity => {
+                let name = names.get(pick.entity).map(Name::as_str).unwrap_or_default();
+                voxel_editor.activate(pick.entity, name);
+                true
+            }
+            _ => false,
+        };
+
+        let prefab_global_transform =
+            ok_or_return!(global_transforms.get(voxel_editor.prefab_entity));
+        let focus_global_transform = ok_or_return!(global_transforms.get(voxel_editor.entity));
+
         // Transform the ray into the local space of focus_transform and cast it.
         let local_ray = {
             let transform = focus_global_transform.affine().inverse();
@@ -62,12 +111,15 @@ pub fn gather_editor_constituents(
             Ray { origin, direction }
         };
 
-        let entity_buffer = entity_buffers.get(voxel_editor.entity).unwrap();
+        let (_, _, entity_buffer) = entity_buffers.get(voxel_editor.entity).unwrap();
         let ray_hit = raycast_buffer_voxels(&entity_buffer.commit_buffer, local_ray);
 
         let drag_origin = {
-            if mouse_button_input.just_pressed(MouseButton::Left)
-                || mouse_button_input.just_pressed(MouseButton::Right)
+            if handled {
+                None
+            } else if !pointer_on_ui
+                && (mouse_button_input.just_pressed(MouseButton::Left)
+                    || mouse_button_input.just_pressed(MouseButton::Right))
             {
                 if let Some(ray_hit) = ray_hit {
                     Some(DragOrigin {
@@ -94,10 +146,11 @@ pub fn gather_editor_constituents(
             world_ray,
             local_ray,
             ray_hit,
+            pick,
             cursor,
             drag_origin,
             mouse_buttons: mouse_button_input.clone(),
-            keyboard: keycode_input.clone(),
+            keyboard: editor_keyboard(&keycode_input, &mut egui_context),
         }
     };
 }
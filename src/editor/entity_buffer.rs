@@ -1,4 +1,9 @@
-use crate::voxel::Buffer;
+use std::collections::HashSet;
+
+use crate::voxel::{
+    Buffer, ChunkCoord, VolumeHash, VoxelChunks, VoxelColliders, VoxelLights, VoxelMaterial,
+    VoxelMeshCache,
+};
 
 pub use bevy::prelude::*;
 
@@ -7,5 +12,178 @@ pub struct EntityBuffer {
     pub buffer_dirty: bool,
     pub buffer: Buffer,
     pub commit_buffer: Buffer,
-    pub undo_stack: Vec<Buffer>,
+
+    /// Chunks whose meshes are out of date with `buffer`. Drained each frame by
+    /// `sync_entity_buffer_meshes`.
+    pub stale_chunks: HashSet<ChunkCoord>,
+
+    /// Chunks whose colliders are out of date with `commit_buffer`. Unlike meshes, colliders are
+    /// only rebuilt when edits are committed. Drained by `sync_entity_buffer_colliders`.
+    pub stale_collider_chunks: HashSet<ChunkCoord>,
+
+    /// Chunks whose lights are out of date with `commit_buffer`. Drained by
+    /// `sync_entity_buffer_lights`.
+    pub stale_light_chunks: HashSet<ChunkCoord>,
+
+    /// The `VolumeHash` of `commit_buffer`, shared by the mesh and collider syncs. Reset whenever
+    /// chunks of it are marked stale.
+    commit_hash: Option<VolumeHash>,
+}
+
+impl EntityBuffer {
+    pub fn new(mut buffer: Buffer) -> Self {
+        // Everything needs to be meshed the first time around.
+        buffer.dirty_chunks.clear();
+        let stale_chunks: HashSet<_> = buffer.chunks.keys().copied().collect();
+
+        Self {
+            buffer_dirty: false,
+            commit_buffer: buffer.clone(),
+            buffer,
+            stale_collider_chunks: stale_chunks.clone(),
+            stale_light_chunks: stale_chunks.clone(),
+            stale_chunks,
+            commit_hash: None,
+        }
+    }
+
+    /// Replaces both buffers with a committed state from the history, marking the chunks that
+    /// differ as stale.
+    pub fn restore(&mut self, buffer: Buffer) {
+        let changed_chunks = self.buffer.changed_chunks(&buffer);
+        self.stale_chunks.extend(changed_chunks.iter().copied());
+        self.mark_committed_stale(changed_chunks);
+        self.commit_buffer = buffer.clone();
+        self.buffer = buffer;
+    }
+
+    /// Marks chunks of `commit_buffer` as stale for everything rebuilt from it (colliders, lights
+    /// and its hash). Call it whenever `commit_buffer` changes.
+    pub fn mark_committed_stale<I>(&mut self, chunks: I)
+    where
+        I: IntoIterator<Item = ChunkCoord>,
+    {
+        for chunk_coord in chunks {
+            self.stale_collider_chunks.insert(chunk_coord);
+            self.stale_light_chunks.insert(chunk_coord);
+        }
+        self.commit_hash = None;
+    }
+
+    /// The `VolumeHash` of `commit_buffer`, only computed again after it changes.
+    pub fn commit_hash(&mut self) -> VolumeHash {
+        *self
+            .commit_hash
+            .get_or_insert_with(|| VolumeHash::of(&self.commit_buffer))
+    }
+}
+
+/// Regenerates the lights of voxel entities whose committed buffer changed. Previews don't move
+/// lights, as respawning them every frame of a drag is costly.
+pub fn sync_entity_buffer_lights(
+    mut commands: Commands,
+    mut query: Query<(Entity, &mut EntityBuffer, &mut VoxelLights)>,
+) {
+    for (entity, mut entity_buffer, mut voxel_lights) in query.iter_mut() {
+        if entity_buffer.stale_light_chunks.is_empty() {
+            continue;
+        }
+
+        let stale_chunks = std::mem::take(&mut entity_buffer.stale_light_chunks);
+        voxel_lights.update(
+            &mut commands,
+            entity,
+            &entity_buffer.commit_buffer,
+            stale_chunks,
+        );
+    }
+}
+
+/// Rebuilds the colliders of stale chunks of every voxel entity, or takes them from the
+/// `VoxelMeshCache` if another entity already built them for the same volume.
+pub fn sync_entity_buffer_colliders(
+    mut commands: Commands,
+    mut cache: ResMut<VoxelMeshCache>,
+    mut query: Query<(Entity, &mut EntityBuffer, &mut VoxelColliders)>,
+) {
+    for (entity, mut entity_buffer, mut voxel_colliders) in query.iter_mut() {
+        if voxel_colliders.needs_full_rebuild {
+            voxel_colliders.needs_full_rebuild = false;
+            let entity_buffer = &mut *entity_buffer;
+            entity_buffer
+                .stale_collider_chunks
+                .extend(entity_buffer.commit_buffer.chunks.keys().copied());
+        }
+
+        if entity_buffer.stale_collider_chunks.is_empty() {
+            continue;
+        }
+
+        let stale_chunks = std::mem::take(&mut entity_buffer.stale_collider_chunks);
+        let key = (entity_buffer.commit_hash(), voxel_colliders.mode);
+
+        match cache.colliders(key) {
+            Some(cached) => voxel_colliders.adopt(&mut commands, entity, cached),
+            None => voxel_colliders.rebuild(
+                &mut commands,
+                entity,
+                &entity_buffer.commit_buffer,
+                stale_chunks,
+            ),
+        }
+        cache.acquire_colliders(entity, key, &voxel_colliders);
+    }
+}
+
+/// Remeshes the stale chunks of every voxel entity. Entities that aren't previewing an edit take
+/// their meshes from the `VoxelMeshCache` if another entity already built them for the same
+/// volume, and otherwise add theirs to it.
+pub fn sync_entity_buffer_meshes(
+    mut commands: Commands,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut images: ResMut<Assets<Image>>,
+    mut materials: ResMut<Assets<VoxelMaterial>>,
+    mut cache: ResMut<VoxelMeshCache>,
+    mut query: Query<(Entity, &mut EntityBuffer, &mut VoxelChunks)>,
+) {
+    for (entity, mut entity_buffer, mut voxel_chunks) in query.iter_mut() {
+        if voxel_chunks.needs_full_remesh {
+            voxel_chunks.needs_full_remesh = false;
+            let entity_buffer = &mut *entity_buffer;
+            entity_buffer
+                .stale_chunks
+                .extend(entity_buffer.buffer.chunks.keys().copied());
+        }
+
+        if entity_buffer.stale_chunks.is_empty() {
+            continue;
+        }
+
+        // Previews change every frame, so only cache what's committed. The buffer is reset to a
+        // clone of the commit buffer each frame, so it shares its chunk map unless edited since.
+        let at_rest = entity_buffer
+            .buffer
+            .chunks
+            .ptr_eq(&entity_buffer.commit_buffer.chunks);
+        let key = at_rest.then(|| (entity_buffer.commit_hash(), voxel_chunks.mesher));
+
+        let stale_chunks = std::mem::take(&mut entity_buffer.stale_chunks);
+        match key.and_then(|key| cache.volume(key)) {
+            Some(cached) => {
+                voxel_chunks.adopt(&mut commands, entity, &entity_buffer.buffer, cached)
+            }
+            None => voxel_chunks.remesh(
+                &mut commands,
+                &mut meshes,
+                entity,
+                &entity_buffer.buffer,
+                stale_chunks,
+            ),
+        }
+
+        if let Some(key) = key {
+            cache.acquire_volume(entity, key, &voxel_chunks);
+        }
+        voxel_chunks.upload_material_table(&mut images, &mut materials);
+    }
 }
@@ -0,0 +1,95 @@
+use bevy::prelude::*;
+use crossbeam_channel::{Receiver, Sender};
+use wasm_bindgen::{closure::Closure, JsCast};
+use web_sys::{Blob, Event, FileReader, HtmlAnchorElement, HtmlInputElement, ProgressEvent, Url};
+
+/// How long after starting a download its object URL is released, in milliseconds.
+const REVOKE_DELAY_MS: i32 = 60_000;
+
+/// A file picked by the user, read into memory.
+pub struct OpenedFile {
+    pub name: String,
+    pub bytes: Vec<u8>,
+}
+
+/// Opens files through the browser's file picker. They arrive, once read, through `try_recv`.
+#[derive(Resource)]
+pub struct FilePicker {
+    tx: Sender<OpenedFile>,
+    rx: Receiver<OpenedFile>,
+}
+
+impl Default for FilePicker {
+    fn default() -> Self {
+        let (tx, rx) = crossbeam_channel::unbounded();
+        Self { tx, rx }
+    }
+}
+
+impl FilePicker {
+    /// Shows the file picker, accepting files with the given extensions (like `[".gpl", ".hex"]`).
+    pub fn open(&self, extensions: &[&str]) {
+        let document = web_sys::window().unwrap().document().unwrap();
+        let input: HtmlInputElement = document
+            .create_element("input")
+            .unwrap()
+            .dyn_into()
+            .unwrap();
+        input.set_type("file");
+        input.set_accept(&extensions.join(","));
+
+        let tx = self.tx.clone();
+        let onchange = Closure::<dyn FnMut(_)>::new(move |e: Event| {
+            let input: HtmlInputElement = e.target().unwrap().dyn_into().unwrap();
+            let file = unwrap_or_return!(input.files().and_then(|files| files.get(0)));
+
+            let name = file.name();
+            let tx = tx.clone();
+            let onload = Closure::<dyn FnMut(_)>::new(move |e: ProgressEvent| {
+                let reader: FileReader = e.target().unwrap().dyn_into().unwrap();
+                let bytes = js_sys::Uint8Array::new(&reader.result().unwrap()).to_vec();
+                let name = name.clone();
+                tx.send(OpenedFile { name, bytes }).ok();
+            });
+
+            let reader = FileReader::new().unwrap();
+            reader.set_onload(Some(onload.as_ref().unchecked_ref()));
+            onload.forget();
+            reader.read_as_array_buffer(&file).unwrap();
+        });
+        input.set_onchange(Some(onchange.as_ref().unchecked_ref()));
+        onchange.forget();
+
+        input.click();
+    }
+
+    /// The next file that finished reading, if any.
+    pub fn try_recv(&self) -> Option<OpenedFile> {
+        self.rx.try_recv().ok()
+    }
+}
+
+/// Offers the bytes to the user as a download named `name`.
+pub fn download_file(name: &str, bytes: &[u8]) {
+    let parts = js_sys::Array::of1(&js_sys::Uint8Array::from(bytes));
+    let blob = Blob::new_with_u8_array_sequence(&parts).unwrap();
+    let url = Url::create_object_url_with_blob(&blob).unwrap();
+
+    let document = web_sys::window().unwrap().document().unwrap();
+    let anchor: HtmlAnchorElement = document.create_element("a").unwrap().dyn_into().unwrap();
+    anchor.set_href(&url);
+    anchor.set_download(name);
+    anchor.click();
+
+    // The download may not have started reading the blob yet, so it's only released later.
+    let revoke = Closure::once_into_js(move || {
+        Url::revoke_object_url(&url).ok();
+    });
+    web_sys::window()
+        .unwrap()
+        .set_timeout_with_callback_and_timeout_and_arguments_0(
+            revoke.unchecked_ref(),
+            REVOKE_DELAY_MS,
+        )
+        .unwrap();
+}
@@ -0,0 +1,275 @@
+use std::collections::{HashMap, VecDeque};
+
+use bevy::prelude::*;
+
+use crate::voxel::Chunk;
+
+use super::command::EditorCommand;
+
+/// How much chunk memory the history may hold on to by default.
+const DEFAULT_BUDGET: usize = 128 * 1024 * 1024;
+
+/// A command in the history, and a description of it for the history panel.
+pub struct HistoryEntry {
+    pub label: String,
+    pub command: EditorCommand,
+}
+
+/// Every edit made in the editor, oldest first, in one timeline across voxels, the hierarchy and
+/// transforms. The first `applied` entries are the ones in effect; undo and redo move that cursor,
+/// and recording a new entry drops everything that was undone.
+///
+/// Buffers are copy-on-write, so voxel entries share the chunks an edit didn't touch. The budget is
+/// checked against the memory of the unique chunks across all entries, dropping the oldest entries
+/// once it's exceeded.
+pub struct History {
+    entries: VecDeque<HistoryEntry>,
+    applied: usize,
+    chunk_refs: ChunkRefs,
+
+    /// Bytes of unique chunk memory the entries may use. The newest entry is always kept.
+    pub budget: usize,
+}
+
+impl Default for History {
+    fn default() -> Self {
+        Self {
+            entries: default(),
+            applied: 0,
+            chunk_refs: default(),
+            budget: DEFAULT_BUDGET,
+        }
+    }
+}
+
+impl History {
+    pub fn entries(&self) -> impl Iterator<Item = &HistoryEntry> {
+        self.entries.iter()
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// The number of entries in effect, so 0 when everything has been undone.
+    pub fn applied(&self) -> usize {
+        self.applied
+    }
+
+    /// Records a command that was just applied, dropping any that were undone.
+    pub fn push(&mut self, label: impl Into<String>, command: EditorCommand) {
+        for entry in self.entries.drain(self.applied..) {
+            self.chunk_refs.remove(&entry.command);
+        }
+        self.chunk_refs.add(&command);
+        self.entries.push_back(HistoryEntry {
+            label: label.into(),
+            command,
+        });
+        self.applied = self.entries.len();
+
+        while self.applied > 1 && self.memory_usage() > self.budget {
+            if let Some(entry) = self.entries.pop_front() {
+                self.chunk_refs.remove(&entry.command);
+            }
+            self.applied -= 1;
+        }
+    }
+
+    /// Like `push`, but folds the command into the newest entry if it changes the same thing.
+    pub fn push_merged(&mut self, label: impl Into<String>, command: EditorCommand) {
+        if self.applied == self.entries.len() {
+            if let Some(last) = self.entries.back_mut() {
+                if last.command.merge(&command) {
+                    last.label = label.into();
+                    return;
+                }
+            }
+        }
+
+        self.push(label, command);
+    }
+
+    /// Steps back an entry, returning the command that undoes it.
+    pub fn undo(&mut self) -> Option<EditorCommand> {
+        if self.applied == 0 {
+            return None;
+        }
+        self.applied -= 1;
+        Some(self.entries[self.applied].command.inverse())
+    }
+
+    /// Steps forward an entry, returning its command to apply again.
+    pub fn redo(&mut self) -> Option<EditorCommand> {
+        let command = self.entries.get(self.applied)?.command.clone();
+        self.applied += 1;
+        Some(command)
+    }
+
+    /// Replaces references to an entity in every entry, as despawned entities come back with a new
+    /// id.
+    pub fn remap(&mut self, from: Entity, to: Entity) {
+        for entry in &mut self.entries {
+            entry.command.remap(from, to);
+        }
+    }
+
+    /// Bytes of memory used by the unique chunks of all entries.
+    pub fn memory_usage(&self) -> usize {
+        self.chunk_refs.bytes
+    }
+}
+
+/// Counts the references the entries hold to each chunk, by address, so the memory of the unique
+/// chunks can be kept up to date as entries come and go.
+#[derive(Default)]
+struct ChunkRefs {
+    counts: HashMap<usize, usize>,
+    bytes: usize,
+}
+
+impl ChunkRefs {
+    fn add(&mut self, command: &EditorCommand) {
+        for chunk in Self::chunks(command) {
+            let count = self
+                .counts
+                .entry(chunk as *const Chunk as usize)
+                .or_default();
+            if *count == 0 {
+                self.bytes += chunk.memory_size();
+            }
+            *count += 1;
+        }
+    }
+
+    fn remove(&mut self, command: &EditorCommand) {
+        for chunk in Self::chunks(command) {
+            let address = chunk as *const Chunk as usize;
+            let count = match self.counts.get_mut(&address) {
+                Some(count) => count,
+                None => continue,
+            };
+            *count -= 1;
+            if *count == 0 {
+                self.counts.remove(&address);
+                self.bytes -= chunk.memory_size();
+            }
+        }
+    }
+
+    fn chunks(command: &EditorCommand) -> impl Iterator<Item = &Chunk> {
+        command
+            .buffers()
+            .into_iter()
+            .flat_map(|buffer| buffer.chunks.values())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::voxel::{Buffer, PbrProps, Rgba, WorldCoord};
+
+    use super::*;
+
+    fn with_voxel(buffer: &Buffer, x: i32) -> Buffer {
+        let mut buffer = buffer.clone();
+        let p = PbrProps {
+            color: Rgba::from(Color::RED),
+            ..default()
+        };
+        buffer.set(WorldCoord(IVec3::new(x, 0, 0)), p);
+        buffer
+    }
+
+    fn voxels(before: &Buffer, after: &Buffer) -> EditorCommand {
+        EditorCommand::Voxels {
+            entity: Entity::from_raw(0),
+            before: before.clone(),
+            after: after.clone(),
+        }
+    }
+
+    fn after_chunks(command: Option<EditorCommand>) -> usize {
+        match command {
+            Some(EditorCommand::Voxels { after, .. }) => after.chunks.len(),
+            _ => panic!("expected a voxel command"),
+        }
+    }
+
+    #[test]
+    fn test_history() {
+        let empty = Buffer::default();
+        let one = with_voxel(&empty, 0);
+        let two = with_voxel(&one, 100);
+
+        let mut history = History::default();
+        history.push("One", voxels(&empty, &one));
+        history.push("Two", voxels(&one, &two));
+        assert_eq!(history.applied(), 2);
+
+        assert_eq!(after_chunks(history.undo()), 1);
+        assert_eq!(after_chunks(history.undo()), 0);
+        assert!(history.undo().is_none());
+        assert_eq!(after_chunks(history.redo()), 1);
+
+        // Recording after an undo drops the undone entries.
+        let other = with_voxel(&one, 200);
+        history.push("Other", voxels(&one, &other));
+        assert!(history.redo().is_none());
+        let labels: Vec<_> = history.entries().map(|e| e.label.as_str()).collect();
+        assert_eq!(labels, vec!["One", "Other"]);
+    }
+
+    #[test]
+    fn test_history_merge() {
+        let entity = Entity::from_raw(0);
+        let moved = |x: f32| EditorCommand::Transform {
+            entity,
+            before: Transform::from_xyz(x - 1.0, 0.0, 0.0),
+            after: Transform::from_xyz(x, 0.0, 0.0),
+        };
+
+        let mut history = History::default();
+        history.push("Move", moved(1.0));
+        history.push_merged("Move", moved(2.0));
+        history.push_merged("Move", moved(3.0));
+        assert_eq!(history.len(), 1);
+
+        match history.undo() {
+            Some(EditorCommand::Transform { after, .. }) => assert_eq!(after.translation.x, 0.0),
+            _ => panic!("expected a transform command"),
+        }
+    }
+
+    #[test]
+    fn test_history_budget() {
+        let one = with_voxel(&Buffer::default(), 0);
+        let chunk_size = one.chunks.values().next().unwrap().memory_size();
+
+        // Edits to the same chunk copy it, so each entry adds a unique chunk to the shared `one`.
+        let mut history = History {
+            budget: chunk_size * 3,
+            ..default()
+        };
+        history.push("A", voxels(&one, &with_voxel(&one, 1)));
+        history.push("B", voxels(&one, &with_voxel(&one, 2)));
+        history.push("C", voxels(&one, &with_voxel(&one, 3)));
+        assert_eq!(history.len(), 2);
+        assert_eq!(history.applied(), 2);
+        assert_eq!(history.memory_usage(), chunk_size * 3);
+
+        // Dropping undone entries releases the chunks only they held.
+        history.undo();
+        history.push("D", voxels(&one, &one));
+        assert_eq!(history.memory_usage(), chunk_size * 2);
+
+        // Entries that share chunks only count them once.
+        let mut history = History::default();
+        history.push("Same", voxels(&one, &one));
+        assert_eq!(history.memory_usage(), chunk_size);
+    }
+}
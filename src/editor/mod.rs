@@ -1,14 +1,36 @@
+mod command;
 mod constituents;
 mod entity_buffer;
+mod file_io;
+mod history;
+mod palette;
+mod selection;
+mod state;
+mod symmetry;
+mod tools;
 mod ui;
 
 use bevy::prelude::*;
 
-use crate::voxel::{Buffer, PbrProps, Rgba, VoxelMaterial, WorldCoord};
+use crate::voxel::{
+    Buffer, DetailPattern, PbrProps, Rgba, VoxelChunks, VoxelColliders, VoxelDetailTextures,
+    VoxelLights, VoxelMaterial, WorldCoord,
+};
 
 use self::{
+    command::{apply_editor_edits, EditorCommand, EditorEdit},
     constituents::{gather_editor_constituents, EditorConstituents},
-    entity_buffer::EntityBuffer,
+    entity_buffer::{
+        sync_entity_buffer_colliders, sync_entity_buffer_lights, sync_entity_buffer_meshes,
+        EntityBuffer,
+    },
+    file_io::FilePicker,
+    history::History,
+    palette::Palette,
+    selection::{draw_selection, Selection},
+    state::{restore_editor_state, save_editor_state, update_registers, EditorState},
+    symmetry::{draw_mirror_planes, Symmetry},
+    tools::{ToolContext, ToolRegistry},
     ui::editor_ui,
 };
 
@@ -16,10 +38,25 @@ pub struct EditorPlugin;
 
 impl Plugin for EditorPlugin {
     fn build(&self, app: &mut App) {
-        app.add_startup_system(setup_test)
+        app.init_resource::<FilePicker>()
+            .add_startup_system(setup_test)
+            .add_startup_system_to_stage(StartupStage::PostStartup, restore_editor_state)
             .add_system(gather_editor_constituents)
             .add_system(editor_ui.after(gather_editor_constituents))
-            .add_system(editor_primary_logic.after(gather_editor_constituents));
+            .add_system(update_registers.before(editor_primary_logic))
+            .add_system(editor_primary_logic.after(gather_editor_constituents))
+            .add_system(draw_selection.after(editor_primary_logic))
+            .add_system(draw_mirror_planes.after(editor_primary_logic))
+            .add_system(import_palette)
+            .add_system(save_editor_state)
+            .add_system(
+                apply_editor_edits
+                    .after(editor_primary_logic)
+                    .after(editor_ui),
+            )
+            .add_system(sync_entity_buffer_lights.after(apply_editor_edits))
+            .add_system(sync_entity_buffer_meshes.after(sync_entity_buffer_lights))
+            .add_system(sync_entity_buffer_colliders.after(apply_editor_edits));
     }
 }
 
@@ -29,12 +66,54 @@ pub struct EditorResource {
     pub prefab_entity: Entity,
     pub entity: Entity,
     pub material: PbrProps,
+    pub tools: ToolRegistry,
+
+    /// Every edit made in the editor, for undo and redo.
+    pub history: History,
+
+    /// The number of history entries to step to, picked in the history panel.
+    pub history_jump: Option<usize>,
+
+    /// Edits requested by the UI, applied by `apply_editor_edits`.
+    pub edits: Vec<EditorEdit>,
+
+    /// The selected voxels of the active entity.
+    pub selection: Selection,
+
+    /// Voxels copied with the select tool.
+    pub clipboard: Buffer,
+
+    /// Mirrors the edits of every tool.
+    pub symmetry: Symmetry,
+
+    /// The palette and registers.
+    pub state: EditorState,
+}
+
+impl EditorResource {
+    /// Makes another entity the one being edited, recording the change in the history.
+    pub fn activate(&mut self, entity: Entity, name: &str) {
+        if entity == self.entity {
+            return;
+        }
+
+        self.history.push(
+            format!("Activate {}", name),
+            EditorCommand::Activate {
+                before: self.entity,
+                after: entity,
+            },
+        );
+        self.entity = entity;
+        self.selection.clear();
+    }
 }
 
 fn setup_test(
     mut commands: Commands,
-    mut meshes: ResMut<Assets<Mesh>>,
     mut materials: ResMut<Assets<VoxelMaterial>>,
+    mut images: ResMut<Assets<Image>>,
+    detail_textures: Res<VoxelDetailTextures>,
 ) {
     let mut buffer = Buffer::default();
     let p = PbrProps {
@@ -43,6 +122,7 @@ fn setup_test(
         roughness: 23,
         reflectance: 128,
         emission: 0,
+        pattern: DetailPattern::Bevel,
     };
 
     // Set all cube alone the outline of the volume (0, 0, 0) to (31, 31, 31)
@@ -63,8 +143,6 @@ fn setup_test(
         buffer.set(WorldCoord((31, 31, x).into()), p);
     }
 
-    let mesh: Mesh = (&buffer).into();
-
     // Spawn an entity tree with names
     let entity = commands
         .spawn((Name::from("Root"), SpatialBundle::default()))
@@ -73,40 +151,26 @@ fn setup_test(
     let child_1 = commands
         .spawn((
             Name::from("Child 1"),
-            MaterialMeshBundle {
-                mesh: meshes.add(mesh.clone()),
-                material: materials.add(VoxelMaterial {}),
-                transform: Transform::from_translation(Vec3::new(-17.0, 0.0, 0.0)),
-                ..default()
-            },
-            EntityBuffer {
-                commit_buffer: buffer.clone(),
-                buffer: buffer.clone(),
-                buffer_dirty: false,
-                undo_stack: default(),
-            },
+            SpatialBundle::from_transform(Transform::from_translation(Vec3::new(-17.0, 0.0, 0.0))),
+            EntityBuffer::new(buffer.clone()),
+            VoxelChunks::new(&mut materials, &mut images, &detail_textures),
+            VoxelLights::default(),
+            VoxelColliders::default(),
         ))
         .id();
 
     let child_2 = commands
         .spawn((
             Name::from("Child 2"),
-            MaterialMeshBundle {
-                mesh: meshes.add(mesh.clone()),
-                material: materials.add(VoxelMaterial {}),
-                transform: Transform {
-                    translation: Vec3::new(17.0, 0.0, 0.0),
-                    rotation: Quat::from_euler(EulerRot::XYZ, 1.0, 1.0, 1.0),
-                    ..default()
-                },
+            SpatialBundle::from_transform(Transform {
+                translation: Vec3::new(17.0, 0.0, 0.0),
+                rotation: Quat::from_euler(EulerRot::XYZ, 1.0, 1.0, 1.0),
                 ..default()
-            },
-            EntityBuffer {
-                commit_buffer: buffer.clone(),
-                buffer: buffer.clone(),
-                buffer_dirty: false,
-                undo_stack: default(),
-            },
+            }),
+            EntityBuffer::new(buffer.clone()),
+            VoxelChunks::new(&mut materials, &mut images, &detail_textures),
+            VoxelLights::default(),
+            VoxelColliders::default(),
         ))
         .id();
 
@@ -117,69 +181,99 @@ fn setup_test(
         prefab_entity: entity,
         entity: child_1,
         material: p,
+        tools: default(),
+        history: default(),
+        history_jump: None,
+        edits: vec![],
+        selection: default(),
+        clipboard: default(),
+        // Mirror across the middle of the test volume.
+        symmetry: Symmetry {
+            plane: Vec3::splat(16.0),
+            ..default()
+        },
+        state: default(),
     });
 }
 
 fn editor_primary_logic(
     mut voxel_editor: ResMut<EditorResource>,
-    mut mesh_handles: Query<&mut Handle<Mesh>>,
-    mut meshes: ResMut<Assets<Mesh>>,
     mut entity_buffers: Query<&mut EntityBuffer>,
 ) {
-    let mouse = voxel_editor.constituents.mouse_buttons.clone();
     let mut entity_buffer = entity_buffers.get_mut(voxel_editor.entity).unwrap();
 
+    // Chunks touched by last frame's preview need remeshing even if this frame doesn't touch them,
+    // as they are about to be reverted.
+    let reverted_chunks = entity_buffer.buffer.take_dirty_chunks();
+
     // Reset the buffer every frame. This is a very cheap operation because of COW semantics.
     entity_buffer.buffer = entity_buffer.commit_buffer.clone();
 
-    if let (Some(drag_origin), Some(ray_hit)) = (
-        voxel_editor.constituents.drag_origin.clone(),
-        voxel_editor.constituents.ray_hit.clone(),
-    ) {
-        // Completely redraw the buffer if either left is down, or if it was just released.
-        if mouse.pressed(MouseButton::Left) || mouse.just_released(MouseButton::Left) {
-            let del = voxel_editor
-                .constituents
-                .keyboard
-                .pressed(KeyCode::LControl);
-
-            let p = if del {
-                default()
-            } else {
-                voxel_editor.material
-            };
-
-            let start = drag_origin.voxel;
-            let end = WorldCoord(ray_hit.world_coord.0 + ray_hit.normal.unwrap_or_default());
-            for world_coord in WorldCoord::iter_range(start, end) {
-                entity_buffer.buffer_dirty = true;
-                entity_buffer.buffer.set(world_coord, p);
-            }
-        }
+    let voxel_editor = &mut *voxel_editor;
+    let mut ctx = ToolContext {
+        constituents: &voxel_editor.constituents,
+        material: voxel_editor.material,
+        selection: &mut voxel_editor.selection,
+        clipboard: &mut voxel_editor.clipboard,
+    };
+    let tool = voxel_editor.tools.active_mut();
 
-        if mouse.just_released(MouseButton::Left) && entity_buffer.buffer_dirty {
-            // Commit the buffer.
-            let undo_buffer = entity_buffer.commit_buffer.clone();
-            entity_buffer.undo_stack.push(undo_buffer);
-            entity_buffer.commit_buffer = entity_buffer.buffer.clone();
-            entity_buffer.buffer_dirty = false;
-        }
+    if ctx.constituents.keyboard.just_pressed(KeyCode::Escape) {
+        tool.cancel();
     }
 
-    // Handle undo (Ctrl + Z)
-    if voxel_editor
-        .constituents
-        .keyboard
-        .pressed(KeyCode::LControl)
-        && voxel_editor.constituents.keyboard.just_pressed(KeyCode::Z)
-    {
-        if let Some(undo_buffer) = entity_buffer.undo_stack.pop() {
-            entity_buffer.commit_buffer = undo_buffer.clone();
-            entity_buffer.buffer = undo_buffer;
-        }
+    // Alt + click samples materials instead of using the tool, see `gather_editor_constituents`.
+    let eyedropper = ctx.constituents.keyboard.pressed(KeyCode::LAlt);
+    if !eyedropper {
+        tool.preview(&mut ctx, &mut entity_buffer.buffer);
+    }
+    if voxel_editor.symmetry.is_enabled() {
+        let entity_buffer = &mut *entity_buffer;
+        voxel_editor
+            .symmetry
+            .mirror(&entity_buffer.commit_buffer, &mut entity_buffer.buffer);
+    }
+    entity_buffer.buffer_dirty = !entity_buffer.buffer.dirty_chunks.is_empty();
+
+    let label = if eyedropper {
+        None
+    } else {
+        tool.commit(&mut ctx)
+    };
+    if let (Some(label), true) = (label, entity_buffer.buffer_dirty) {
+        // Commit the buffer.
+        let entity_buffer = &mut *entity_buffer;
+        let dirty_chunks = entity_buffer.buffer.dirty_chunks.clone();
+        entity_buffer.mark_committed_stale(dirty_chunks);
+        let mut commit_buffer = entity_buffer.buffer.clone();
+        commit_buffer.dirty_chunks.clear();
+        let before = std::mem::replace(&mut entity_buffer.commit_buffer, commit_buffer.clone());
+        entity_buffer.buffer_dirty = false;
+
+        voxel_editor.history.push(
+            label,
+            EditorCommand::Voxels {
+                entity: voxel_editor.entity,
+                before,
+                after: commit_buffer,
+            },
+        );
     }
 
     // Finalize
-    *mesh_handles.get_mut(voxel_editor.entity).unwrap() =
-        meshes.add((&entity_buffer.buffer).into());
+    let entity_buffer = &mut *entity_buffer;
+    entity_buffer.stale_chunks.extend(reverted_chunks);
+    entity_buffer
+        .stale_chunks
+        .extend(entity_buffer.buffer.dirty_chunks.iter().copied());
+}
+
+/// Replaces the palette with palette files opened from the palette panel.
+fn import_palette(mut voxel_editor: ResMut<EditorResource>, file_picker: Res<FilePicker>) {
+    while let Some(file) = file_picker.try_recv() {
+        match Palette::import(&file.name, &file.bytes) {
+            Ok(palette) => voxel_editor.state.palette = palette,
+            Err(error) => error!("failed to import palette {}: {}", file.name, error),
+        }
+    }
 }
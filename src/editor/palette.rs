@@ -0,0 +1,267 @@
+use std::fmt;
+
+use bevy::{
+    prelude::*,
+    render::{
+        render_resource::TextureFormat,
+        texture::{CompressedImageFormats, ImageType},
+    },
+};
+use serde::{Deserialize, Serialize};
+
+use crate::voxel::{PbrProps, Rgba};
+
+/// A named material in a palette.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct Swatch {
+    pub name: String,
+    pub props: PbrProps,
+}
+
+impl Swatch {
+    /// An opaque swatch of a plain color, named after its hex code if `name` is empty.
+    fn from_rgb(name: &str, r: u8, g: u8, b: u8) -> Self {
+        let name = if name.is_empty() {
+            format!("#{:02x}{:02x}{:02x}", r, g, b)
+        } else {
+            name.to_string()
+        };
+
+        Self {
+            name,
+            props: PbrProps {
+                color: Rgba { r, g, b, a: 255 },
+                ..default()
+            },
+        }
+    }
+}
+
+/// A named list of swatches, saved as RON and importable from common palette files.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct Palette {
+    pub name: String,
+    pub swatches: Vec<Swatch>,
+}
+
+#[derive(Debug)]
+pub enum PaletteError {
+    /// The file extension isn't one of the supported palette formats.
+    UnknownFormat(String),
+    /// A line of a text palette couldn't be parsed. Lines count from 1.
+    Parse {
+        line: usize,
+        message: String,
+    },
+    Ron(ron::error::SpannedError),
+    Image(String),
+}
+
+impl fmt::Display for PaletteError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PaletteError::UnknownFormat(extension) => {
+                write!(f, "unknown palette format '{}'", extension)
+            }
+            PaletteError::Parse { line, message } => write!(f, "line {}: {}", line, message),
+            PaletteError::Ron(error) => write!(f, "{}", error),
+            PaletteError::Image(message) => write!(f, "{}", message),
+        }
+    }
+}
+
+impl Palette {
+    /// The file extensions `import` understands.
+    pub const EXTENSIONS: [&'static str; 4] = ["ron", "gpl", "hex", "png"];
+
+    /// Reads a palette in the format given by the extension of `file_name`.
+    pub fn import(file_name: &str, bytes: &[u8]) -> Result<Self, PaletteError> {
+        let (stem, extension) = file_name.rsplit_once('.').unwrap_or((file_name, ""));
+        let text = || String::from_utf8_lossy(bytes);
+
+        match extension.to_lowercase().as_str() {
+            "ron" => Self::from_ron(&text()),
+            "gpl" => Self::from_gpl(&text()),
+            "hex" => Self::from_hex(stem, &text()),
+            "png" => Self::from_png_strip(stem, bytes),
+            extension => Err(PaletteError::UnknownFormat(extension.to_string())),
+        }
+    }
+
+    pub fn from_ron(text: &str) -> Result<Self, PaletteError> {
+        ron::from_str(text).map_err(PaletteError::Ron)
+    }
+
+    pub fn to_ron(&self) -> String {
+        ron::ser::to_string_pretty(self, default()).unwrap()
+    }
+
+    /// Reads a GIMP palette: a "GIMP Palette" header, then "R G B Name" lines.
+    pub fn from_gpl(text: &str) -> Result<Self, PaletteError> {
+        let mut lines = text
+            .lines()
+            .enumerate()
+            .map(|(index, line)| (index + 1, line.trim()))
+            .filter(|(_, line)| !line.is_empty());
+
+        match lines.next() {
+            Some((_, "GIMP Palette")) => {}
+            _ => {
+                return Err(PaletteError::Parse {
+                    line: 1,
+                    message: "missing 'GIMP Palette' header".into(),
+                })
+            }
+        }
+
+        let mut palette = Palette::default();
+        for (line, text) in lines {
+            if text.starts_with('#') || text.starts_with("Columns:") {
+                continue;
+            }
+            if let Some(name) = text.strip_prefix("Name:") {
+                palette.name = name.trim().to_string();
+                continue;
+            }
+
+            let mut fields = text.split_whitespace();
+            let mut channel = || {
+                fields
+                    .next()
+                    .and_then(|field| field.parse::<u8>().ok())
+                    .ok_or_else(|| PaletteError::Parse {
+                        line,
+                        message: "expected 3 color channels from 0 to 255".into(),
+                    })
+            };
+            let (r, g, b) = (channel()?, channel()?, channel()?);
+            let name = fields.collect::<Vec<_>>().join(" ");
+            palette.swatches.push(Swatch::from_rgb(&name, r, g, b));
+        }
+
+        Ok(palette)
+    }
+
+    /// Reads a palette of one RRGGBB hex color per line, with or without a leading '#'.
+    pub fn from_hex(name: &str, text: &str) -> Result<Self, PaletteError> {
+        let mut palette = Palette {
+            name: name.to_string(),
+            ..default()
+        };
+
+        for (index, text) in text.lines().enumerate() {
+            let hex = text.trim().trim_start_matches('#');
+            if hex.is_empty() {
+                continue;
+            }
+
+            // from_str_radix alone would also take a sign.
+            let valid = hex.len() == 6 && hex.bytes().all(|b| b.is_ascii_hexdigit());
+            let color = match u32::from_str_radix(hex, 16) {
+                Ok(color) if valid => color,
+                _ => {
+                    return Err(PaletteError::Parse {
+                        line: index + 1,
+                        message: format!("'{}' isn't a RRGGBB color", text.trim()),
+                    })
+                }
+            };
+            let [_, r, g, b] = color.to_be_bytes();
+            palette.swatches.push(Swatch::from_rgb("", r, g, b));
+        }
+
+        Ok(palette)
+    }
+
+    /// Reads a PNG swatch strip: the colors along the middle row of the image, in order. Runs of
+    /// the same color are one swatch, and transparent pixels separate swatches.
+    pub fn from_png_strip(name: &str, bytes: &[u8]) -> Result<Self, PaletteError> {
+        let image = Image::from_buffer(
+            bytes,
+            ImageType::Extension("png"),
+            CompressedImageFormats::NONE,
+            true,
+        )
+        .map_err(|error| PaletteError::Image(error.to_string()))?;
+
+        if !matches!(
+            image.texture_descriptor.format,
+            TextureFormat::Rgba8UnormSrgb | TextureFormat::Rgba8Unorm
+        ) {
+            return Err(PaletteError::Image(format!(
+                "unsupported pixel format {:?}",
+                image.texture_descriptor.format
+            )));
+        }
+
+        let size = image.texture_descriptor.size;
+        let row_length = size.width as usize * 4;
+        let row_start = (size.height / 2) as usize * row_length;
+        let row = &image.data[row_start..row_start + row_length];
+
+        let mut palette = Palette {
+            name: name.to_string(),
+            ..default()
+        };
+        let mut previous = None;
+        for pixel in row.chunks_exact(4) {
+            let [r, g, b, a] = [pixel[0], pixel[1], pixel[2], pixel[3]];
+            if a != 0 && previous != Some([r, g, b, a]) {
+                palette.swatches.push(Swatch::from_rgb("", r, g, b));
+            }
+            previous = Some([r, g, b, a]);
+        }
+
+        Ok(palette)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_from_gpl() {
+        let text =
+            "GIMP Palette\nName: Sunset\nColumns: 4\n#\n255   0   0\tRed\n  0 128 255 Sky Blue\n";
+        let palette = Palette::from_gpl(text).unwrap();
+        assert_eq!(palette.name, "Sunset");
+        assert_eq!(palette.swatches.len(), 2);
+        assert_eq!(palette.swatches[0].name, "Red");
+        assert_eq!(palette.swatches[1].name, "Sky Blue");
+        assert_eq!(
+            palette.swatches[1].props.color,
+            Rgba {
+                r: 0,
+                g: 128,
+                b: 255,
+                a: 255
+            }
+        );
+
+        assert!(Palette::from_gpl("255 0 0 Red").is_err());
+        assert!(matches!(
+            Palette::from_gpl("GIMP Palette\n255 0\n"),
+            Err(PaletteError::Parse { line: 2, .. })
+        ));
+    }
+
+    #[test]
+    fn test_from_hex() {
+        let palette = Palette::import("pico.hex", b"ff0000\n#00ff80\n\n").unwrap();
+        assert_eq!(palette.name, "pico");
+        assert_eq!(palette.swatches.len(), 2);
+        assert_eq!(palette.swatches[1].name, "#00ff80");
+        assert_eq!(palette.swatches[1].props.color.g, 255);
+
+        assert!(Palette::from_hex("bad", "ff00").is_err());
+        assert!(Palette::from_hex("bad", "+fffff").is_err());
+        assert!(Palette::import("palette.txt", b"").is_err());
+    }
+
+    #[test]
+    fn test_ron_round_trip() {
+        let palette = Palette::from_hex("pico", "ff0000\n00ff80").unwrap();
+        assert_eq!(Palette::from_ron(&palette.to_ron()).unwrap(), palette);
+    }
+}
@@ -0,0 +1,96 @@
+use std::collections::HashSet;
+
+use bevy::prelude::*;
+use bevy_prototype_debug_lines::DebugLines;
+
+use crate::voxel::{Buffer, WorldCoord};
+
+use super::EditorResource;
+
+/// A set of voxels of the active entity, which copy/paste and other edits operate on.
+#[derive(Clone, Default)]
+pub struct Selection {
+    voxels: HashSet<WorldCoord>,
+}
+
+impl Selection {
+    pub fn is_empty(&self) -> bool {
+        self.voxels.is_empty()
+    }
+
+    pub fn len(&self) -> usize {
+        self.voxels.len()
+    }
+
+    pub fn contains(&self, coord: WorldCoord) -> bool {
+        self.voxels.contains(&coord)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = WorldCoord> + '_ {
+        self.voxels.iter().copied()
+    }
+
+    pub fn clear(&mut self) {
+        self.voxels.clear();
+    }
+
+    pub fn extend<I>(&mut self, coords: I)
+    where
+        I: IntoIterator<Item = WorldCoord>,
+    {
+        self.voxels.extend(coords);
+    }
+
+    /// The smallest and largest coordinates of the selection, if it isn't empty.
+    pub fn bounds(&self) -> Option<(IVec3, IVec3)> {
+        let mut coords = self.voxels.iter().map(|coord| coord.0);
+        let first = coords.next()?;
+        Some(coords.fold((first, first), |(min, max), coord| {
+            (min.min(coord), max.max(coord))
+        }))
+    }
+
+    /// Copies the selected voxels of the buffer into a new one, relative to the smallest corner of
+    /// the selection.
+    pub fn copy(&self, buffer: &Buffer) -> Buffer {
+        let mut copy = Buffer::default();
+        if let Some((min, _)) = self.bounds() {
+            for coord in self.iter() {
+                copy.set(WorldCoord(coord.0 - min), buffer.get(coord));
+            }
+            copy.dirty_chunks.clear();
+        }
+        copy
+    }
+
+    /// Empties the selected voxels of the buffer.
+    pub fn erase(&self, buffer: &mut Buffer) {
+        for coord in self.iter() {
+            buffer.set(coord, default());
+        }
+    }
+}
+
+/// Outlines the bounds of the selection of the active entity.
+pub fn draw_selection(voxel_editor: Res<EditorResource>, mut lines: ResMut<DebugLines>) {
+    let (min, max) = unwrap_or_return!(voxel_editor.selection.bounds());
+    let transform = voxel_editor.constituents.focus_global_transform;
+    let (min, max) = (min.as_vec3(), max.as_vec3() + Vec3::ONE);
+
+    let corner = |i: usize| {
+        transform.transform_point(Vec3::new(
+            if i & 1 == 0 { min.x } else { max.x },
+            if i & 2 == 0 { min.y } else { max.y },
+            if i & 4 == 0 { min.z } else { max.z },
+        ))
+    };
+
+    // Each edge connects two corners that differ in one bit.
+    for i in 0..8 {
+        for bit in [1, 2, 4] {
+            if i & bit == 0 {
+                lines.line_colored(corner(i), corner(i | bit), 0.0, Color::YELLOW);
+            }
+        }
+    }
+}
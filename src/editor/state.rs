@@ -0,0 +1,197 @@
+use bevy::prelude::*;
+use bevy_egui::EguiContext;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    camera::{CameraController, CameraParams},
+    voxel::PbrProps,
+};
+
+use super::{
+    constituents::editor_keyboard, entity_buffer::EntityBuffer, palette::Palette,
+    tools::ToolOptions, EditorResource,
+};
+
+/// Where the editor state is kept: the local storage key on the web, the file name natively.
+const STORAGE_KEY: &str = "editor_state.ron";
+
+/// How often the editor state is saved, in seconds. It's only written when it changed.
+const SAVE_INTERVAL: f32 = 2.0;
+
+/// The number keys of the registers, in register order.
+const REGISTER_KEYS: [KeyCode; 10] = [
+    KeyCode::Key1,
+    KeyCode::Key2,
+    KeyCode::Key3,
+    KeyCode::Key4,
+    KeyCode::Key5,
+    KeyCode::Key6,
+    KeyCode::Key7,
+    KeyCode::Key8,
+    KeyCode::Key9,
+    KeyCode::Key0,
+];
+
+/// Editor state worth keeping between sessions. Saved to local storage on the web, and to a file
+/// in the working directory natively. Fields missing from an older save keep their defaults.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct EditorState {
+    pub palette: Palette,
+
+    /// Materials bound to the number keys 1 to 9, then 0. Shift + the key stores the current
+    /// material, the key alone loads it.
+    pub registers: [Option<PbrProps>; 10],
+
+    // The rest is copied from the rest of the editor when saving, and back when restoring.
+    pub material: Option<PbrProps>,
+
+    /// The name of the entity being edited. Only the entity: which room is open isn't saved, the
+    /// editor has no rooms to restore.
+    pub active_entity: Option<String>,
+
+    pub camera: Option<CameraParams>,
+    pub panel_width: Option<f32>,
+    pub tools: ToolOptions,
+}
+
+impl EditorState {
+    /// Reads the saved state, if there is one that parses.
+    pub fn load() -> Option<Self> {
+        let text = read_storage()?;
+        match ron::from_str(&text) {
+            Ok(state) => Some(state),
+            Err(error) => {
+                warn!("ignoring saved editor state: {}", error);
+                None
+            }
+        }
+    }
+
+    pub fn to_ron(&self) -> String {
+        ron::ser::to_string_pretty(self, default()).unwrap()
+    }
+
+    /// The label of the key of a register.
+    pub fn register_key(index: usize) -> usize {
+        (index + 1) % 10
+    }
+
+    /// Stores or loads registers for the number keys pressed this frame.
+    pub fn handle_register_keys(&mut self, keyboard: &Input<KeyCode>, material: &mut PbrProps) {
+        let shift = keyboard.any_pressed([KeyCode::LShift, KeyCode::RShift]);
+        for (register, key) in self.registers.iter_mut().zip(REGISTER_KEYS) {
+            if !keyboard.just_pressed(key) {
+                continue;
+            }
+
+            if shift {
+                *register = Some(*material);
+            } else if let Some(props) = register {
+                *material = *props;
+            }
+        }
+    }
+}
+
+#[cfg(target_arch = "wasm32")]
+fn read_storage() -> Option<String> {
+    let storage = web_sys::window()?.local_storage().ok()??;
+    storage.get_item(STORAGE_KEY).ok()?
+}
+
+#[cfg(target_arch = "wasm32")]
+fn write_storage(text: &str) {
+    let storage = web_sys::window().and_then(|window| window.local_storage().ok().flatten());
+    if let Some(Err(error)) = storage.map(|storage| storage.set_item(STORAGE_KEY, text)) {
+        warn!("failed to save editor state: {:?}", error);
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn read_storage() -> Option<String> {
+    std::fs::read_to_string(STORAGE_KEY).ok()
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn write_storage(text: &str) {
+    if let Err(error) = std::fs::write(STORAGE_KEY, text) {
+        warn!("failed to save editor state: {}", error);
+    }
+}
+
+/// Stores or loads registers for the number keys, unless they're typed into an egui text field
+/// (like the palette's swatch names).
+pub fn update_registers(
+    mut voxel_editor: ResMut<EditorResource>,
+    keycode_input: Res<Input<KeyCode>>,
+    mut egui_context: ResMut<EguiContext>,
+) {
+    let keyboard = editor_keyboard(&keycode_input, &mut egui_context);
+    let voxel_editor = &mut *voxel_editor;
+    voxel_editor
+        .state
+        .handle_register_keys(&keyboard, &mut voxel_editor.material);
+}
+
+/// Restores the saved editor state, once the editor and camera are set up.
+pub fn restore_editor_state(
+    mut voxel_editor: ResMut<EditorResource>,
+    mut camera_controller: ResMut<CameraController>,
+    entity_buffers: Query<(Entity, &Name), With<EntityBuffer>>,
+) {
+    let state = unwrap_or_return!(EditorState::load());
+    let voxel_editor = &mut *voxel_editor;
+
+    if let Some(material) = state.material {
+        voxel_editor.material = material;
+    }
+    if let Some(camera) = state.camera {
+        camera_controller.set_params(camera);
+    }
+    if let Some(width) = state.panel_width {
+        camera_controller.margins.left = width;
+    }
+    voxel_editor.tools.load_options(&state.tools);
+
+    // Refocuses the entity of that name, if it exists. Not recorded in the history, there's
+    // nothing to undo to yet.
+    let active = entity_buffers
+        .iter()
+        .find(|(_, name)| Some(name.as_str()) == state.active_entity.as_deref());
+    if let Some((entity, _)) = active {
+        voxel_editor.entity = entity;
+    }
+
+    voxel_editor.state = state;
+}
+
+/// Saves the editor state every `SAVE_INTERVAL` seconds, if it changed.
+pub fn save_editor_state(
+    mut voxel_editor: ResMut<EditorResource>,
+    camera_controller: Res<CameraController>,
+    names: Query<&Name>,
+    time: Res<Time>,
+    mut since_save: Local<f32>,
+    mut saved: Local<String>,
+) {
+    *since_save += time.delta_seconds();
+    if *since_save < SAVE_INTERVAL {
+        return;
+    }
+    *since_save = 0.0;
+
+    let voxel_editor = &mut *voxel_editor;
+    let state = &mut voxel_editor.state;
+    state.material = Some(voxel_editor.material);
+    state.active_entity = names.get(voxel_editor.entity).ok().map(Name::to_string);
+    state.camera = Some(camera_controller.params());
+    state.panel_width = Some(camera_controller.margins.left);
+    state.tools = voxel_editor.tools.save_options();
+
+    let text = state.to_ron();
+    if text != *saved {
+        write_storage(&text);
+        *saved = text;
+    }
+}
@@ -0,0 +1,184 @@
+use bevy::prelude::*;
+use bevy_prototype_debug_lines::DebugLines;
+
+use crate::voxel::{Buffer, PbrProps, WorldCoord};
+
+use super::EditorResource;
+
+/// Half the width of the squares drawn for mirror planes.
+const PLANE_EXTENT: f32 = 24.0;
+
+/// Mirrors every edit of the active entity across planes perpendicular to the enabled axes.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Symmetry {
+    /// Whether edits are mirrored across the X, Y and Z planes.
+    pub axes: [bool; 3],
+
+    /// Where the mirror planes cross their axes, in the local space of the active entity. Rounded
+    /// to half voxels, so planes lie either between voxels or through their middle.
+    pub plane: Vec3,
+}
+
+impl Symmetry {
+    pub fn is_enabled(&self) -> bool {
+        self.axes.contains(&true)
+    }
+
+    /// The reflections of the voxel across every combination of the enabled planes.
+    pub fn mirrors(&self, coord: WorldCoord) -> impl Iterator<Item = WorldCoord> {
+        // The voxel spanning `c..c + 1` mirrors to `2 * plane - c - 1`.
+        let doubled = (self.plane * 2.0).round().as_ivec3();
+        let axes = self.axes;
+
+        (1..8)
+            .filter(move |mask| (0..3).all(|axis| mask & (1 << axis) == 0 || axes[axis]))
+            .map(move |mask| {
+                let mut mirrored = coord.0;
+                for axis in 0..3 {
+                    if mask & (1 << axis) != 0 {
+                        mirrored[axis] = doubled[axis] - mirrored[axis] - 1;
+                    }
+                }
+                WorldCoord(mirrored)
+            })
+    }
+
+    /// Copies every voxel of `buffer` that differs from `before` to its reflections. Voxels that
+    /// were recolored rather than added or erased only recolor reflections that exist, so painting
+    /// doesn't add voxels on the other side.
+    pub fn mirror(&self, before: &Buffer, buffer: &mut Buffer) {
+        let mut changed: Vec<(WorldCoord, PbrProps, bool)> = vec![];
+        for chunk_coord in buffer.dirty_chunks.iter() {
+            let chunk = buffer.chunks.get(chunk_coord);
+            let old_chunk = before.chunks.get(chunk_coord);
+
+            // Neighbors of edited chunks are dirty too, without having changed.
+            if chunk.map(|c| &c.voxels) == old_chunk.map(|c| &c.voxels) {
+                continue;
+            }
+
+            for coord in chunk_coord.iter_world_coords() {
+                let props = chunk.map(|c| c.get(coord)).unwrap_or_default();
+                let old_props = old_chunk.map(|c| c.get(coord)).unwrap_or_default();
+                if props != old_props {
+                    let recolored = !props.is_empty() && !old_props.is_empty();
+                    changed.push((coord, props, recolored));
+                }
+            }
+        }
+
+        for (coord, props, recolored) in changed {
+            for mirrored in self.mirrors(coord) {
+                if recolored && before.get(mirrored).is_empty() {
+                    continue;
+                }
+                buffer.set(mirrored, props);
+            }
+        }
+    }
+}
+
+/// Draws the enabled mirror planes of the active entity.
+pub fn draw_mirror_planes(voxel_editor: Res<EditorResource>, mut lines: ResMut<DebugLines>) {
+    let symmetry = voxel_editor.symmetry;
+    let transform = voxel_editor.constituents.focus_global_transform;
+    let colors = [Color::RED, Color::BLUE, Color::GREEN];
+
+    for axis in (0..3).filter(|axis| symmetry.axes[*axis]) {
+        // The two axes the plane spans.
+        let (u, v) = ((axis + 1) % 3, (axis + 2) % 3);
+        let corner = |su: f32, sv: f32| {
+            let mut point = symmetry.plane;
+            point[u] += su * PLANE_EXTENT;
+            point[v] += sv * PLANE_EXTENT;
+            transform.transform_point(point)
+        };
+
+        let corners = [
+            corner(-1.0, -1.0),
+            corner(1.0, -1.0),
+            corner(1.0, 1.0),
+            corner(-1.0, 1.0),
+        ];
+        for i in 0..4 {
+            lines.line_colored(corners[i], corners[(i + 1) % 4], 0.0, colors[axis]);
+        }
+        lines.line_colored(corners[0], corners[2], 0.0, colors[axis]);
+        lines.line_colored(corners[1], corners[3], 0.0, colors[axis]);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::voxel::Rgba;
+
+    use super::*;
+
+    #[test]
+    fn test_mirrors() {
+        let symmetry = Symmetry {
+            axes: [true, false, true],
+            plane: Vec3::new(16.0, 0.0, 0.5),
+        };
+
+        let mirrors: Vec<_> = symmetry.mirrors(WorldCoord(IVec3::new(15, 3, 2))).collect();
+        assert_eq!(
+            mirrors,
+            vec![
+                WorldCoord(IVec3::new(16, 3, 2)),
+                WorldCoord(IVec3::new(15, 3, -2)),
+                WorldCoord(IVec3::new(16, 3, -2)),
+            ]
+        );
+
+        // Edits are copied to their reflections, including erasing.
+        let p = PbrProps {
+            color: Rgba::from(Color::RED),
+            ..default()
+        };
+        let mut before = Buffer::default();
+        before.set(WorldCoord(IVec3::new(15, 3, 2)), p);
+        for mirrored in mirrors {
+            before.set(mirrored, p);
+        }
+        before.dirty_chunks.clear();
+
+        let mut buffer = before.clone();
+        buffer.set(WorldCoord(IVec3::new(15, 3, 2)), default());
+        buffer.set(WorldCoord(IVec3::new(20, 0, 0)), p);
+        symmetry.mirror(&before, &mut buffer);
+        assert_eq!(buffer.count(), 2);
+        assert_eq!(buffer.get(WorldCoord(IVec3::new(11, 0, 0))), p);
+    }
+
+    #[test]
+    fn test_mirror_paint() {
+        let symmetry = Symmetry {
+            axes: [true, false, false],
+            plane: Vec3::new(0.0, 0.0, 0.0),
+        };
+        let red = PbrProps {
+            color: Rgba::from(Color::RED),
+            ..default()
+        };
+        let blue = PbrProps {
+            color: Rgba::from(Color::BLUE),
+            ..default()
+        };
+
+        // `(2, 0, 0)` reflects to the voxel at `(-3, 0, 0)`, `(3, 0, 0)` to nothing.
+        let mut before = Buffer::default();
+        before.set(WorldCoord(IVec3::new(2, 0, 0)), red);
+        before.set(WorldCoord(IVec3::new(-3, 0, 0)), red);
+        before.set(WorldCoord(IVec3::new(3, 0, 0)), red);
+        before.dirty_chunks.clear();
+
+        let mut buffer = before.clone();
+        buffer.set(WorldCoord(IVec3::new(2, 0, 0)), blue);
+        buffer.set(WorldCoord(IVec3::new(3, 0, 0)), blue);
+        symmetry.mirror(&before, &mut buffer);
+        assert_eq!(buffer.count(), 3);
+        assert_eq!(buffer.get(WorldCoord(IVec3::new(-3, 0, 0))), blue);
+        assert!(buffer.get(WorldCoord(IVec3::new(-4, 0, 0))).is_empty());
+    }
+}
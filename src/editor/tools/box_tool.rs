@@ -0,0 +1,82 @@
+use bevy::prelude::*;
+use egui::Ui;
+
+use crate::voxel::{Buffer, WorldCoord};
+
+use super::{EditorTool, ToolContext};
+
+/// Drag with the left mouse button to fill the box from the voxel the drag started on to the one in
+/// front of the voxel under the cursor. Holding Left Control erases the box instead.
+#[derive(Default)]
+pub struct BoxTool {
+    /// Set by `cancel`, ignoring the rest of the drag until the button is released.
+    cancelled: bool,
+}
+
+impl EditorTool for BoxTool {
+    fn name(&self) -> &'static str {
+        "Box"
+    }
+
+    fn preview(&mut self, ctx: &mut ToolContext, buffer: &mut Buffer) {
+        let constituents = ctx.constituents;
+        let mouse = &constituents.mouse_buttons;
+
+        // Still cancelled on the frame the button is released, so that frame isn't committed.
+        if self.cancelled {
+            if !mouse.pressed(MouseButton::Left) && !mouse.just_released(MouseButton::Left) {
+                self.cancelled = false;
+            }
+            return;
+        }
+
+        let (drag_origin, ray_hit) = match (&constituents.drag_origin, &constituents.ray_hit) {
+            (Some(drag_origin), Some(ray_hit)) => (drag_origin, ray_hit),
+            _ => return,
+        };
+
+        // Completely redraw the buffer if either left is down, or if it was just released.
+        if !mouse.pressed(MouseButton::Left) && !mouse.just_released(MouseButton::Left) {
+            return;
+        }
+
+        let del = constituents.keyboard.pressed(KeyCode::LControl);
+        let p = if del { default() } else { ctx.material };
+
+        let start = drag_origin.voxel;
+        let end = WorldCoord(ray_hit.world_coord.0 + ray_hit.normal.unwrap_or_default());
+        for world_coord in WorldCoord::iter_range(start, end) {
+            buffer.set(world_coord, p);
+        }
+    }
+
+    fn commit(&mut self, ctx: &mut ToolContext) -> Option<String> {
+        let constituents = ctx.constituents;
+        if self.cancelled || !constituents.mouse_buttons.just_released(MouseButton::Left) {
+            return None;
+        }
+
+        let (drag_origin, ray_hit) = match (&constituents.drag_origin, &constituents.ray_hit) {
+            (Some(drag_origin), Some(ray_hit)) => (drag_origin, ray_hit),
+            _ => return None,
+        };
+
+        let end = ray_hit.world_coord.0 + ray_hit.normal.unwrap_or_default();
+        let size = (end - drag_origin.voxel.0).abs() + IVec3::ONE;
+        let action = if constituents.keyboard.pressed(KeyCode::LControl) {
+            "erase"
+        } else {
+            "fill"
+        };
+
+        Some(format!("Box {} {}x{}x{}", action, size.x, size.y, size.z))
+    }
+
+    fn cancel(&mut self) {
+        self.cancelled = true;
+    }
+
+    fn ui(&mut self, ui: &mut Ui) {
+        ui.label("Drag to fill a box. Hold Left Control to erase.");
+    }
+}
@@ -0,0 +1,131 @@
+use bevy::prelude::*;
+use egui::Ui;
+
+use crate::voxel::{coplanar_faces, Buffer, PbrProps, WorldCoord};
+
+use super::{EditorTool, ToolContext};
+
+/// Drag a face with the left mouse button to extrude it, and every face connected to it in the same
+/// plane with the same material, along its normal. Dragging the other way pushes the faces in.
+#[derive(Default)]
+pub struct ExtrudeTool {
+    drag: Option<ExtrudeDrag>,
+
+    /// Set by `cancel`, ignoring the rest of the drag until the button is released.
+    cancelled: bool,
+}
+
+struct ExtrudeDrag {
+    faces: Vec<(WorldCoord, PbrProps)>,
+    normal: IVec3,
+
+    /// Where the face was grabbed, in the local space of the buffer.
+    origin: Vec3,
+
+    /// How many voxels the faces are moved out, negative when pushed in.
+    amount: i32,
+}
+
+impl ExtrudeDrag {
+    /// The distance along the normal from the grab point to the point of that line closest to the
+    /// ray, or `None` if the ray runs along the line.
+    fn distance_to(&self, ray: Ray) -> Option<f32> {
+        let normal = self.normal.as_vec3();
+        let w = self.origin - ray.origin;
+        let b = normal.dot(ray.direction);
+        let denominator = 1.0 - b * b;
+        if denominator < 1e-4 {
+            return None;
+        }
+
+        Some((b * ray.direction.dot(w) - normal.dot(w)) / denominator)
+    }
+}
+
+impl EditorTool for ExtrudeTool {
+    fn name(&self) -> &'static str {
+        "Extrude"
+    }
+
+    fn preview(&mut self, ctx: &mut ToolContext, buffer: &mut Buffer) {
+        let constituents = ctx.constituents;
+        let mouse = &constituents.mouse_buttons;
+
+        if self.cancelled {
+            if !mouse.pressed(MouseButton::Left) && !mouse.just_released(MouseButton::Left) {
+                self.cancelled = false;
+            }
+            return;
+        }
+
+        if mouse.just_pressed(MouseButton::Left) {
+            self.drag = None;
+            let drag_origin = unwrap_or_return!(&constituents.drag_origin);
+            let ray_hit = drag_origin.ray_hit;
+            let normal = unwrap_or_return!(ray_hit.normal);
+
+            let faces = coplanar_faces(buffer, ray_hit.world_coord, normal)
+                .into_iter()
+                .map(|coord| (coord, buffer.get(coord)))
+                .collect();
+
+            self.drag = Some(ExtrudeDrag {
+                faces,
+                normal,
+                origin: drag_origin.local_ray.origin
+                    + drag_origin.local_ray.direction * ray_hit.distance,
+                amount: 0,
+            });
+        }
+
+        let drag = unwrap_or_return!(&mut self.drag);
+        if !mouse.pressed(MouseButton::Left) && !mouse.just_released(MouseButton::Left) {
+            self.drag = None;
+            return;
+        }
+
+        if let Some(distance) = drag.distance_to(constituents.local_ray) {
+            drag.amount = distance.round() as i32;
+        }
+
+        for (coord, props) in &drag.faces {
+            if drag.amount > 0 {
+                for step in 1..=drag.amount {
+                    buffer.set(WorldCoord(coord.0 + drag.normal * step), *props);
+                }
+            } else {
+                for step in 0..-drag.amount {
+                    buffer.set(WorldCoord(coord.0 - drag.normal * step), default());
+                }
+            }
+        }
+    }
+
+    fn commit(&mut self, ctx: &mut ToolContext) -> Option<String> {
+        if !ctx
+            .constituents
+            .mouse_buttons
+            .just_released(MouseButton::Left)
+        {
+            return None;
+        }
+
+        let drag = self.drag.take()?;
+        match drag.amount {
+            0 => None,
+            amount if amount > 0 => {
+                Some(format!("Extrude {} faces by {}", drag.faces.len(), amount))
+            }
+            amount => Some(format!("Push in {} faces by {}", drag.faces.len(), -amount)),
+        }
+    }
+
+    fn cancel(&mut self) {
+        self.drag = None;
+        self.cancelled = true;
+    }
+
+    fn ui(&mut self, ui: &mut Ui) {
+        ui.label("Drag a face along its normal to extrude it, or against it to push it in.");
+    }
+}
@@ -0,0 +1,182 @@
+mod box_tool;
+mod extrude_tool;
+mod paint_tool;
+mod select_tool;
+
+use std::collections::BTreeMap;
+
+use egui::Ui;
+use serde::{Deserialize, Serialize};
+
+use crate::voxel::{Buffer, PbrProps};
+
+use super::{constituents::EditorConstituents, selection::Selection};
+
+pub use box_tool::*;
+pub use extrude_tool::*;
+pub use paint_tool::*;
+pub use select_tool::*;
+
+/// Everything a tool gets to look at each frame, and the editor state tools share.
+pub struct ToolContext<'a> {
+    pub constituents: &'a EditorConstituents,
+
+    /// The material picked in the editor UI.
+    pub material: PbrProps,
+
+    /// The selected voxels of the active entity.
+    pub selection: &'a mut Selection,
+
+    /// Copied voxels, relative to the smallest corner of what was copied.
+    pub clipboard: &'a mut Buffer,
+}
+
+/// A way of editing the voxels of the active entity. Each frame the editor resets the ephemeral
+/// buffer to the committed one and has the active tool preview its edit into it, then commits the
+/// ephemeral buffer if the tool says so. Tools never touch the committed buffer themselves.
+pub trait EditorTool: Send + Sync {
+    fn name(&self) -> &'static str;
+
+    /// Called when the tool becomes the active one.
+    fn activate(&mut self) {}
+
+    /// Draws this frame's edit into `buffer`, which starts out as a copy of the committed buffer.
+    fn preview(&mut self, ctx: &mut ToolContext, buffer: &mut Buffer);
+
+    /// Whether this frame's preview should be committed, returning the label of the history entry
+    /// if so.
+    fn commit(&mut self, ctx: &mut ToolContext) -> Option<String>;
+
+    /// Abandons the edit in progress, if any. Called on Escape and when another tool is activated.
+    fn cancel(&mut self) {}
+
+    /// Draws the tool's options in the editor panel.
+    fn ui(&mut self, _ui: &mut Ui) {}
+
+    /// The tool's options as RON, kept between sessions.
+    fn save_options(&self) -> Option<String> {
+        None
+    }
+
+    /// Restores options returned by `save_options`, ignoring them if they no longer parse.
+    fn load_options(&mut self, _options: &str) {}
+}
+
+/// The active tool and the options of every tool, by tool name.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct ToolOptions {
+    pub active: String,
+    pub options: BTreeMap<String, String>,
+}
+
+/// The tools of the editor, one of which is active.
+pub struct ToolRegistry {
+    tools: Vec<Box<dyn EditorTool>>,
+    active: usize,
+}
+
+impl Default for ToolRegistry {
+    fn default() -> Self {
+        let mut registry = Self {
+            tools: vec![],
+            active: 0,
+        };
+
+        registry.register(BoxTool::default());
+        registry.register(SelectTool::default());
+        registry.register(ExtrudeTool::default());
+        registry.register(PaintTool::default());
+        registry
+    }
+}
+
+impl ToolRegistry {
+    pub fn register<T>(&mut self, tool: T)
+    where
+        T: EditorTool + 'static,
+    {
+        self.tools.push(Box::new(tool));
+    }
+
+    pub fn names(&self) -> impl Iterator<Item = &'static str> + '_ {
+        self.tools.iter().map(|tool| tool.name())
+    }
+
+    pub fn active_index(&self) -> usize {
+        self.active
+    }
+
+    pub fn active_mut(&mut self) -> &mut dyn EditorTool {
+        self.tools[self.active].as_mut()
+    }
+
+    /// Cancels whatever the current tool was doing and activates another.
+    pub fn set_active(&mut self, index: usize) {
+        if index == self.active || index >= self.tools.len() {
+            return;
+        }
+
+        self.tools[self.active].cancel();
+        self.active = index;
+        self.tools[self.active].activate();
+    }
+
+    pub fn save_options(&self) -> ToolOptions {
+        ToolOptions {
+            active: self.tools[self.active].name().to_string(),
+            options: self
+                .tools
+                .iter()
+                .filter_map(|tool| Some((tool.name().to_string(), tool.save_options()?)))
+                .collect(),
+        }
+    }
+
+    /// Restores the active tool and options saved by `save_options`. Tools that no longer exist
+    /// are skipped.
+    pub fn load_options(&mut self, options: &ToolOptions) {
+        for tool in self.tools.iter_mut() {
+            if let Some(tool_options) = options.options.get(tool.name()) {
+                tool.load_options(tool_options);
+            }
+        }
+
+        let active = self.names().position(|name| name == options.active);
+        if let Some(index) = active {
+            self.set_active(index);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tool_options() {
+        let mut tools = ToolRegistry::default();
+        let defaults = tools.save_options();
+        let paint = tools.names().position(|name| name == "Paint").unwrap();
+
+        tools.set_active(paint);
+        tools.active_mut().load_options(
+            "(Sphere, 7, (color: true, opacity: false, metallic: true, roughness: true, \
+             reflectance: true, emission: true, pattern: false))",
+        );
+        let mut options = tools.save_options();
+        assert_eq!(options.active, "Paint");
+        assert_ne!(options.options["Paint"], defaults.options["Paint"]);
+        options
+            .options
+            .insert("Select".to_string(), "not ron".to_string());
+
+        let mut restored = ToolRegistry::default();
+        restored.load_options(&options);
+        assert_eq!(restored.active_index(), paint);
+
+        // Options that don't parse leave the defaults.
+        let saved = restored.save_options();
+        assert_eq!(saved.options["Paint"], options.options["Paint"]);
+        assert_eq!(saved.options["Select"], defaults.options["Select"]);
+    }
+}
@@ -0,0 +1,256 @@
+use std::collections::HashSet;
+
+use bevy::prelude::*;
+use egui::{Slider, Ui};
+use serde::{Deserialize, Serialize};
+
+use crate::voxel::{Buffer, PbrProps, Rgba, WorldCoord};
+
+use super::{EditorTool, ToolContext};
+
+/// Which voxels a paint stroke covers.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum PaintShape {
+    /// The voxel under the cursor.
+    #[default]
+    Voxel,
+    /// The box from the voxel the drag started on to the one under the cursor.
+    Box,
+    /// The voxels within `radius` of the one under the cursor.
+    Sphere,
+    /// Like `Sphere`, but only the voxels showing a face on the side that was hit.
+    Surface,
+}
+
+/// The channels of `PbrProps` that painting replaces. The rest keep what the voxel had.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PaintMask {
+    pub color: bool,
+    pub opacity: bool,
+    pub metallic: bool,
+    pub roughness: bool,
+    pub reflectance: bool,
+    pub emission: bool,
+    pub pattern: bool,
+}
+
+impl Default for PaintMask {
+    fn default() -> Self {
+        Self {
+            color: true,
+            opacity: true,
+            metallic: true,
+            roughness: true,
+            reflectance: true,
+            emission: true,
+            pattern: true,
+        }
+    }
+}
+
+impl PaintMask {
+    /// The voxel with the masked channels taken from the material.
+    pub fn apply(&self, voxel: PbrProps, material: PbrProps) -> PbrProps {
+        let pick = |masked: bool, voxel: u8, material: u8| if masked { material } else { voxel };
+
+        PbrProps {
+            color: Rgba {
+                r: pick(self.color, voxel.color.r, material.color.r),
+                g: pick(self.color, voxel.color.g, material.color.g),
+                b: pick(self.color, voxel.color.b, material.color.b),
+                a: pick(self.opacity, voxel.color.a, material.color.a),
+            },
+            metallic: pick(self.metallic, voxel.metallic, material.metallic),
+            roughness: pick(self.roughness, voxel.roughness, material.roughness),
+            reflectance: pick(self.reflectance, voxel.reflectance, material.reflectance),
+            emission: pick(self.emission, voxel.emission, material.emission),
+            pattern: if self.pattern {
+                material.pattern
+            } else {
+                voxel.pattern
+            },
+        }
+    }
+}
+
+/// Drag with the left mouse button to paint existing voxels with the material, without changing
+/// their shape. When there's a selection, only selected voxels are painted, and Enter paints all of
+/// them.
+pub struct PaintTool {
+    pub shape: PaintShape,
+    pub radius: u32,
+    pub mask: PaintMask,
+
+    /// The voxels painted so far by the current stroke.
+    stroke: HashSet<WorldCoord>,
+
+    /// The label of this frame's edit, set by `preview` for `commit` to return.
+    pending: Option<String>,
+
+    /// Set by `cancel`, ignoring the rest of the drag until the button is released.
+    cancelled: bool,
+}
+
+impl Default for PaintTool {
+    fn default() -> Self {
+        Self {
+            shape: default(),
+            radius: 3,
+            mask: default(),
+            stroke: default(),
+            pending: None,
+            cancelled: false,
+        }
+    }
+}
+
+impl PaintTool {
+    /// Paints the voxel if there is one, returning whether it changed.
+    fn paint(&self, buffer: &mut Buffer, coord: WorldCoord, material: PbrProps) -> bool {
+        let voxel = buffer.get(coord);
+        let painted = self.mask.apply(voxel, material);
+        if voxel.is_empty() || painted == voxel {
+            return false;
+        }
+
+        buffer.set(coord, painted);
+        true
+    }
+}
+
+impl EditorTool for PaintTool {
+    fn name(&self) -> &'static str {
+        "Paint"
+    }
+
+    fn preview(&mut self, ctx: &mut ToolContext, buffer: &mut Buffer) {
+        let constituents = ctx.constituents;
+        let mouse = &constituents.mouse_buttons;
+        self.pending = None;
+
+        if self.cancelled {
+            if !mouse.pressed(MouseButton::Left) && !mouse.just_released(MouseButton::Left) {
+                self.cancelled = false;
+            }
+            return;
+        }
+
+        if constituents.keyboard.just_pressed(KeyCode::Return) && !ctx.selection.is_empty() {
+            for coord in ctx.selection.iter() {
+                self.paint(buffer, coord, ctx.material);
+            }
+            self.pending = Some("Paint selection".into());
+            return;
+        }
+
+        if mouse.just_pressed(MouseButton::Left) {
+            self.stroke.clear();
+        }
+        if !mouse.pressed(MouseButton::Left) && !mouse.just_released(MouseButton::Left) {
+            return;
+        }
+
+        // Boxes are redrawn from the drag origin each frame, the rest add to the stroke.
+        if let Some(ray_hit) = &constituents.ray_hit {
+            let hit = ray_hit.world_coord;
+            let radius = self.radius as i32;
+            let sphere = || {
+                WorldCoord::iter_range(
+                    WorldCoord(hit.0 - IVec3::splat(radius)),
+                    WorldCoord(hit.0 + IVec3::splat(radius)),
+                )
+                .filter(move |coord| {
+                    let offset = coord.0 - hit.0;
+                    offset.dot(offset) <= radius * radius
+                })
+            };
+
+            match self.shape {
+                PaintShape::Voxel => {
+                    self.stroke.insert(hit);
+                }
+                PaintShape::Box => {
+                    if let Some(drag_origin) = &constituents.drag_origin {
+                        self.stroke.clear();
+                        self.stroke
+                            .extend(WorldCoord::iter_range(drag_origin.voxel, hit));
+                    }
+                }
+                PaintShape::Sphere => self.stroke.extend(sphere()),
+                PaintShape::Surface => {
+                    if let Some(normal) = ray_hit.normal {
+                        let surface = sphere()
+                            .filter(|coord| buffer.get(WorldCoord(coord.0 + normal)).is_empty());
+                        self.stroke.extend(surface);
+                    }
+                }
+            }
+        }
+
+        let selection = &ctx.selection;
+        let stroke = self
+            .stroke
+            .iter()
+            .filter(|coord| selection.is_empty() || selection.contains(**coord));
+
+        let mut painted = 0;
+        for coord in stroke {
+            if self.paint(buffer, *coord, ctx.material) {
+                painted += 1;
+            }
+        }
+
+        if mouse.just_released(MouseButton::Left) {
+            self.stroke.clear();
+            self.pending = Some(format!("Paint {} voxels", painted));
+        }
+    }
+
+    fn commit(&mut self, _ctx: &mut ToolContext) -> Option<String> {
+        self.pending.take()
+    }
+
+    fn cancel(&mut self) {
+        self.stroke.clear();
+        self.cancelled = true;
+    }
+
+    fn ui(&mut self, ui: &mut Ui) {
+        ui.horizontal(|ui| {
+            ui.radio_value(&mut self.shape, PaintShape::Voxel, "Voxel");
+            ui.radio_value(&mut self.shape, PaintShape::Box, "Box");
+            ui.radio_value(&mut self.shape, PaintShape::Sphere, "Sphere");
+            ui.radio_value(&mut self.shape, PaintShape::Surface, "Surface");
+        });
+
+        if matches!(self.shape, PaintShape::Sphere | PaintShape::Surface) {
+            ui.add(Slider::new(&mut self.radius, 0..=16).text("Radius"));
+        }
+
+        ui.label("Channels");
+        ui.horizontal_wrapped(|ui| {
+            let mask = &mut self.mask;
+            ui.checkbox(&mut mask.color, "Color");
+            ui.checkbox(&mut mask.opacity, "Opacity");
+            ui.checkbox(&mut mask.metallic, "Metallic");
+            ui.checkbox(&mut mask.roughness, "Roughness");
+            ui.checkbox(&mut mask.reflectance, "Reflectance");
+            ui.checkbox(&mut mask.emission, "Emission");
+            ui.checkbox(&mut mask.pattern, "Pattern");
+        });
+
+        ui.label("Drag to paint. With a selection, only selected voxels are painted and Enter paints them all.");
+    }
+
+    fn save_options(&self) -> Option<String> {
+        ron::to_string(&(self.shape, self.radius, self.mask)).ok()
+    }
+
+    fn load_options(&mut self, options: &str) {
+        if let Ok((shape, radius, mask)) = ron::from_str(options) {
+            self.shape = shape;
+            self.radius = radius;
+            self.mask = mask;
+        }
+    }
+}
@@ -0,0 +1,296 @@
+use bevy::prelude::*;
+use egui::{Slider, Ui};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    editor::selection::Selection,
+    voxel::{flood_fill, Buffer, PbrProps, VoxelStorage, WorldCoord, FACE_NEIGHBORS},
+};
+
+use super::{EditorTool, ToolContext};
+
+/// How clicking and dragging selects voxels.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum SelectMode {
+    /// The voxels in the box from the voxel the drag started on to the one under the cursor.
+    #[default]
+    Box,
+    /// The voxels connected to the clicked one with a similar material.
+    Wand,
+    /// Every voxel with a material similar to the clicked one.
+    Material,
+}
+
+/// Drag with the left mouse button to select the voxels in a box, or click to select by material,
+/// holding Shift to add to the selection. The selection can be copied, cut, pasted, deleted and
+/// moved; pasted and moved voxels float until they're placed.
+#[derive(Default)]
+pub struct SelectTool {
+    pub mode: SelectMode,
+
+    /// How much any channel of a material may differ from the clicked one to be selected by the
+    /// wand and material modes.
+    pub tolerance: u8,
+
+    floating: Option<Floating>,
+
+    /// The label of this frame's edit, set by `preview` for `commit` to return.
+    pending: Option<&'static str>,
+
+    /// Set when the current drag shouldn't select, until the button is released.
+    ignore_drag: bool,
+}
+
+/// Voxels being pasted or moved, drawn into the preview until they're placed.
+struct Floating {
+    /// Relative to the smallest corner of their bounds.
+    voxels: Vec<(IVec3, PbrProps)>,
+
+    /// Where the smallest corner goes, if anywhere yet.
+    position: Option<IVec3>,
+
+    /// Whether `position` follows the voxel in front of the one under the cursor.
+    follow_cursor: bool,
+
+    /// Moved by the arrow keys, and added to `position`.
+    nudge: IVec3,
+
+    /// Voxels emptied when placed, being the source of a move.
+    lifted: Selection,
+
+    label: &'static str,
+}
+
+impl Floating {
+    fn new<I>(voxels: I, label: &'static str) -> Self
+    where
+        I: IntoIterator<Item = (IVec3, PbrProps)>,
+    {
+        let mut floating = Self {
+            voxels: voxels.into_iter().collect(),
+            position: None,
+            follow_cursor: true,
+            nudge: IVec3::ZERO,
+            lifted: default(),
+            label,
+        };
+
+        floating.normalize();
+        floating
+    }
+
+    /// Moves the voxels back so their smallest corner is at the origin.
+    fn normalize(&mut self) {
+        let min = self
+            .voxels
+            .iter()
+            .map(|(coord, _)| *coord)
+            .reduce(IVec3::min)
+            .unwrap_or_default();
+
+        for (coord, _) in &mut self.voxels {
+            *coord -= min;
+        }
+    }
+
+    /// Rotates the voxels a quarter turn around the Y axis.
+    fn rotate(&mut self) {
+        for (coord, _) in &mut self.voxels {
+            *coord = IVec3::new(coord.z, coord.y, -coord.x);
+        }
+        self.normalize();
+    }
+
+    /// Mirrors the voxels along the axis.
+    fn flip(&mut self, axis: usize) {
+        for (coord, _) in &mut self.voxels {
+            coord[axis] = -coord[axis];
+        }
+        self.normalize();
+    }
+
+    fn handle_keys(&mut self, keyboard: &Input<KeyCode>) {
+        let shift = keyboard.pressed(KeyCode::LShift);
+        let nudges = [
+            (KeyCode::Left, IVec3::NEG_X),
+            (KeyCode::Right, IVec3::X),
+            (KeyCode::Up, IVec3::NEG_Z),
+            (KeyCode::Down, IVec3::Z),
+            (KeyCode::PageUp, IVec3::Y),
+            (KeyCode::PageDown, IVec3::NEG_Y),
+        ];
+
+        for (key, nudge) in nudges {
+            if keyboard.just_pressed(key) {
+                self.nudge += nudge;
+            }
+        }
+
+        if keyboard.just_pressed(KeyCode::R) {
+            self.rotate();
+        }
+        if keyboard.just_pressed(KeyCode::F) {
+            self.flip(if shift { 2 } else { 0 });
+        }
+    }
+
+    /// The voxels where they'd be placed right now.
+    fn placed(&self) -> impl Iterator<Item = (WorldCoord, PbrProps)> + '_ {
+        let offset = self.position.map(|position| position + self.nudge);
+        offset.into_iter().flat_map(move |offset| {
+            self.voxels
+                .iter()
+                .map(move |(coord, props)| (WorldCoord(*coord + offset), *props))
+        })
+    }
+}
+
+impl EditorTool for SelectTool {
+    fn name(&self) -> &'static str {
+        "Select"
+    }
+
+    fn preview(&mut self, ctx: &mut ToolContext, buffer: &mut Buffer) {
+        let constituents = ctx.constituents;
+        let keyboard = &constituents.keyboard;
+        let mouse = &constituents.mouse_buttons;
+        let ctrl = keyboard.pressed(KeyCode::LControl);
+
+        self.pending = None;
+        if self.ignore_drag && !mouse.pressed(MouseButton::Left) {
+            self.ignore_drag = mouse.just_released(MouseButton::Left);
+        }
+
+        if let Some(floating) = &mut self.floating {
+            floating.handle_keys(keyboard);
+            if floating.follow_cursor {
+                if let Some(ray_hit) = &constituents.ray_hit {
+                    floating.position =
+                        Some(ray_hit.world_coord.0 + ray_hit.normal.unwrap_or_default());
+                }
+            }
+
+            floating.lifted.erase(buffer);
+            for (coord, props) in floating.placed() {
+                buffer.set(coord, props);
+            }
+
+            let place =
+                mouse.just_pressed(MouseButton::Left) || keyboard.just_pressed(KeyCode::Return);
+            if place && floating.position.is_some() {
+                // The placed voxels become the selection.
+                ctx.selection.clear();
+                ctx.selection
+                    .extend(floating.placed().map(|(coord, _)| coord));
+                self.pending = Some(floating.label);
+                self.floating = None;
+                self.ignore_drag = true;
+            }
+            return;
+        }
+
+        // Copying nothing keeps what was copied before.
+        let copy = ctrl && !ctx.selection.is_empty();
+        if copy && keyboard.just_pressed(KeyCode::C) {
+            *ctx.clipboard = ctx.selection.copy(buffer);
+        } else if copy && keyboard.just_pressed(KeyCode::X) {
+            *ctx.clipboard = ctx.selection.copy(buffer);
+            ctx.selection.erase(buffer);
+            ctx.selection.clear();
+            self.pending = Some("Cut");
+        } else if keyboard.just_pressed(KeyCode::Delete) {
+            ctx.selection.erase(buffer);
+            ctx.selection.clear();
+            self.pending = Some("Delete selection");
+        } else if ctrl && keyboard.just_pressed(KeyCode::V) {
+            let voxels = ctx
+                .clipboard
+                .voxels()
+                .map(|(coord, props)| (coord.0, props));
+            self.floating = Some(Floating::new(voxels, "Paste"));
+        } else if keyboard.just_pressed(KeyCode::M) {
+            let (min, _) = unwrap_or_return!(ctx.selection.bounds());
+            let voxels = ctx
+                .selection
+                .iter()
+                .map(|coord| (coord.0, buffer.get(coord)));
+            let mut floating = Floating::new(voxels, "Move");
+            floating.position = Some(min);
+            floating.follow_cursor = false;
+            floating.lifted = ctx.selection.clone();
+            self.floating = Some(floating);
+        }
+
+        if self.ignore_drag || !mouse.just_released(MouseButton::Left) {
+            return;
+        }
+
+        if !keyboard.pressed(KeyCode::LShift) {
+            ctx.selection.clear();
+        }
+
+        // Clicking empty space only clears the selection.
+        let (drag_origin, ray_hit) = match (&constituents.drag_origin, &constituents.ray_hit) {
+            (Some(drag_origin), Some(ray_hit)) => (drag_origin, ray_hit),
+            _ => return,
+        };
+
+        let seed = ray_hit.props;
+        let similar = |p: PbrProps| !p.is_empty() && p.difference(&seed) <= self.tolerance;
+        let selected: Vec<_> = match self.mode {
+            SelectMode::Box => WorldCoord::iter_range(drag_origin.voxel, ray_hit.world_coord)
+                .filter(|coord| !buffer.get(*coord).is_empty())
+                .collect(),
+            SelectMode::Wand => flood_fill(buffer, ray_hit.world_coord, &FACE_NEIGHBORS, |_, p| {
+                similar(p)
+            }),
+            SelectMode::Material => buffer
+                .voxels()
+                .filter(|(_, p)| similar(*p))
+                .map(|(coord, _)| coord)
+                .collect(),
+        };
+        ctx.selection.extend(selected);
+    }
+
+    fn commit(&mut self, _ctx: &mut ToolContext) -> Option<String> {
+        self.pending.take().map(String::from)
+    }
+
+    fn cancel(&mut self) {
+        self.floating = None;
+        self.ignore_drag = true;
+    }
+
+    fn ui(&mut self, ui: &mut Ui) {
+        ui.horizontal(|ui| {
+            ui.radio_value(&mut self.mode, SelectMode::Box, "Box");
+            ui.radio_value(&mut self.mode, SelectMode::Wand, "Magic wand");
+            ui.radio_value(&mut self.mode, SelectMode::Material, "By material");
+        });
+
+        if self.mode != SelectMode::Box {
+            ui.add(Slider::new(&mut self.tolerance, 0..=255).text("Tolerance"));
+        }
+
+        ui.label("Drag or click to select, holding Shift to add to the selection.");
+        ui.label("Ctrl+C, Ctrl+X and Ctrl+V copy, cut and paste. Delete erases, M moves.");
+        if self.floating.is_some() {
+            ui.label(
+                "Arrows and Page Up/Down nudge, R rotates, F (Shift+F) flips along X (Z). \
+                 Click or press Enter to place.",
+            );
+        }
+    }
+
+    fn save_options(&self) -> Option<String> {
+        ron::to_string(&(self.mode, self.tolerance)).ok()
+    }
+
+    fn load_options(&mut self, options: &str) {
+        if let Ok((mode, tolerance)) = ron::from_str(options) {
+            self.mode = mode;
+            self.tolerance = tolerance;
+        }
+    }
+}
@@ -3,34 +3,112 @@ use bevy_egui::EguiContext;
 use egui::{
     color::Hsva,
     color_picker::{color_picker_hsva_2d, Alpha},
-    CollapsingHeader, Slider, Ui,
+    Button, CollapsingHeader, Color32, ComboBox, DragValue, Slider, Stroke, Ui,
 };
 
-use crate::{camera::CameraController, voxel::Rgba};
+use crate::{
+    camera::CameraController,
+    voxel::{
+        DetailPattern, Mesher, PbrProps, Rgba, VoxelChunkStats, VoxelChunks, VoxelColliderMode,
+        VoxelColliders, VoxelMeshCache,
+    },
+};
+
+use super::{
+    command::EditorEdit,
+    entity_buffer::EntityBuffer,
+    file_io::{download_file, FilePicker},
+    history::History,
+    palette::{Palette, Swatch},
+    state::EditorState,
+    symmetry::Symmetry,
+    tools::ToolRegistry,
+    EditorResource,
+};
 
-use super::{entity_buffer::EntityBuffer, EditorResource};
+/// The size of swatch and register buttons.
+const SWATCH_SIZE: [f32; 2] = [20.0, 20.0];
 
+#[allow(clippy::too_many_arguments)]
 pub fn editor_ui(
     mut voxel_editor: ResMut<EditorResource>,
     mut egui_context: ResMut<EguiContext>,
     mut camera_controller: ResMut<CameraController>,
+    file_picker: Res<FilePicker>,
     ui_query: Query<(&Name, Option<&Children>, Option<&EntityBuffer>)>,
     entity_buffers: Query<&EntityBuffer>,
+    transforms: Query<(&Transform, Option<&Parent>)>,
+    mut voxel_chunks: Query<&mut VoxelChunks>,
+    mut voxel_colliders: Query<&mut VoxelColliders>,
+    chunk_stats: Res<VoxelChunkStats>,
+    mesh_cache: Res<VoxelMeshCache>,
 ) {
     let entity_buffer = entity_buffers.get(voxel_editor.entity).unwrap();
     camera_controller.margins.left = egui::SidePanel::left("left_panel")
         .resizable(true)
+        .default_width(camera_controller.margins.left)
         .show(egui_context.ctx_mut(), |ui| {
             ui.label(format!("Entity: {:?}", voxel_editor.entity));
             ui.label(format!("Prefab Entity: {:?}", voxel_editor.prefab_entity));
             ui.label(format!("Buffer dirty: {}", entity_buffer.buffer_dirty));
-            ui.label(format!("Undo stack: {}", entity_buffer.undo_stack.len()));
+            ui.label(format!(
+                "Chunks drawn: {} / {} ({} occluded)",
+                chunk_stats.drawn, chunk_stats.total, chunk_stats.occluded
+            ));
+            ui.label(format!("Cached volumes: {}", mesh_cache.volume_count()));
+            ui.label(format!("Selected voxels: {}", voxel_editor.selection.len()));
+
+            ui.separator();
+            draw_toolbar(ui, &mut voxel_editor.tools);
+            ui.separator();
+
+            if let Some(index) = draw_history(ui, &voxel_editor.history) {
+                voxel_editor.history_jump = Some(index);
+            }
+
+            draw_inspector(ui, &mut voxel_editor, &ui_query, &transforms);
+            draw_symmetry(ui, &mut voxel_editor.symmetry);
+
+            if let Ok(mut voxel_chunks) = voxel_chunks.get_mut(voxel_editor.entity) {
+                let mut smooth = voxel_chunks.mesher == Mesher::Smooth;
+                if ui.checkbox(&mut smooth, "Smooth surfaces").changed() {
+                    voxel_chunks.set_mesher(if smooth {
+                        Mesher::Smooth
+                    } else {
+                        Mesher::Blocky
+                    });
+                }
+
+                ui.checkbox(&mut voxel_chunks.occlusion_culling, "Occlusion culling");
+            }
+
+            if let Ok(mut voxel_colliders) = voxel_colliders.get_mut(voxel_editor.entity) {
+                let mut trimesh = voxel_colliders.mode == VoxelColliderMode::TriMesh;
+                if ui
+                    .checkbox(&mut trimesh, "Triangle mesh collider")
+                    .changed()
+                {
+                    voxel_colliders.set_mode(if trimesh {
+                        VoxelColliderMode::TriMesh
+                    } else {
+                        VoxelColliderMode::Cuboids
+                    });
+                }
+            }
 
             let color = Color::from(voxel_editor.material.color).as_rgba_f32();
             let mut hsva = Hsva::from_rgb([color[0], color[1], color[2]]);
             color_picker_hsva_2d(ui, &mut hsva, Alpha::Opaque);
             let color = hsva.to_rgb();
+            let opacity = voxel_editor.material.color.a;
             voxel_editor.material.color = Rgba::from(Color::rgb(color[0], color[1], color[2]));
+            voxel_editor.material.color.a = opacity;
+
+            ui.add(
+                Slider::new(&mut voxel_editor.material.color.a, 1..=255)
+                    .smart_aim(true)
+                    .text("Opacity"),
+            );
 
             ui.add(
                 Slider::new(&mut voxel_editor.material.metallic, (0)..=(255))
@@ -56,6 +134,17 @@ pub fn editor_ui(
                     .text("Reflectance"),
             );
 
+            ui.horizontal(|ui| {
+                ui.label("Detail");
+                let pattern = &mut voxel_editor.material.pattern;
+                ui.radio_value(pattern, DetailPattern::None, "None");
+                ui.radio_value(pattern, DetailPattern::Panel, "Panel");
+                ui.radio_value(pattern, DetailPattern::Bevel, "Bevel");
+            });
+
+            let editor = &mut *voxel_editor;
+            draw_palette(ui, &mut editor.state, &mut editor.material, &file_picker);
+
             let prefab = voxel_editor.prefab_entity;
             draw_entity_tree(ui, &mut voxel_editor, prefab, &ui_query);
         })
@@ -64,14 +153,252 @@ pub fn editor_ui(
         .width();
 }
 
+fn draw_toolbar(ui: &mut Ui, tools: &mut ToolRegistry) {
+    let active = tools.active_index();
+    let mut clicked = None;
+    ui.horizontal(|ui| {
+        for (index, name) in tools.names().enumerate() {
+            if ui.selectable_label(index == active, name).clicked() {
+                clicked = Some(index);
+            }
+        }
+    });
+
+    if let Some(index) = clicked {
+        tools.set_active(index);
+    }
+
+    tools.active_mut().ui(ui);
+}
+
+/// Lists the history entries, returning the number of entries to step to if one was clicked.
+fn draw_history(ui: &mut Ui, history: &History) -> Option<usize> {
+    let mut clicked = None;
+    CollapsingHeader::new("History").show(ui, |ui| {
+        ui.label(format!(
+            "Memory: {:.1} / {:.1} MiB",
+            history.memory_usage() as f32 / (1024.0 * 1024.0),
+            history.budget as f32 / (1024.0 * 1024.0)
+        ));
+
+        // Clicking an entry steps to just after it, so the first row undoes everything.
+        let labels = std::iter::once("Initial").chain(history.entries().map(|e| e.label.as_str()));
+        for (applied, label) in labels.enumerate() {
+            if ui
+                .selectable_label(applied == history.applied(), label)
+                .clicked()
+            {
+                clicked = Some(applied);
+            }
+        }
+
+        if history.is_empty() {
+            ui.label("Nothing to undo");
+        }
+    });
+
+    clicked
+}
+
+fn draw_symmetry(ui: &mut Ui, symmetry: &mut Symmetry) {
+    CollapsingHeader::new("Symmetry").show(ui, |ui| {
+        for (axis, name) in ["X", "Y", "Z"].into_iter().enumerate() {
+            ui.horizontal(|ui| {
+                ui.checkbox(&mut symmetry.axes[axis], format!("Mirror {}", name));
+                ui.add(DragValue::new(&mut symmetry.plane[axis]).speed(0.5));
+            });
+        }
+
+        // Planes lie between voxels or through their middle.
+        symmetry.plane = (symmetry.plane * 2.0).round() / 2.0;
+    });
+}
+
+/// A button showing the color of a material, outlined when `selected`.
+fn swatch_button(ui: &mut Ui, props: PbrProps, selected: bool) -> egui::Response {
+    let Rgba { r, g, b, .. } = props.color;
+    let mut button = Button::new("").fill(Color32::from_rgb(r, g, b));
+    if selected {
+        button = button.stroke(Stroke::new(2.0, Color32::WHITE));
+    }
+
+    ui.add_sized(SWATCH_SIZE, button)
+}
+
+/// Draws the palette and registers. Clicking a swatch or register loads its material.
+fn draw_palette(
+    ui: &mut Ui,
+    state: &mut EditorState,
+    material: &mut PbrProps,
+    file_picker: &FilePicker,
+) {
+    CollapsingHeader::new("Palette").show(ui, |ui| {
+        let palette = &mut state.palette;
+        ui.horizontal(|ui| {
+            ui.label("Name");
+            ui.text_edit_singleline(&mut palette.name);
+        });
+
+        let selected = palette.swatches.iter().position(|s| s.props == *material);
+        ui.horizontal_wrapped(|ui| {
+            for (index, swatch) in palette.swatches.iter().enumerate() {
+                if swatch_button(ui, swatch.props, selected == Some(index))
+                    .on_hover_text(&swatch.name)
+                    .clicked()
+                {
+                    *material = swatch.props;
+                }
+            }
+        });
+
+        if let Some(index) = selected {
+            ui.horizontal(|ui| {
+                ui.label("Swatch");
+                ui.text_edit_singleline(&mut palette.swatches[index].name);
+            });
+        }
+
+        ui.horizontal(|ui| {
+            if ui.button("Add").clicked() {
+                palette.swatches.push(Swatch {
+                    name: format!("Swatch {}", palette.swatches.len() + 1),
+                    props: *material,
+                });
+            }
+            if let (Some(index), true) = (selected, ui.button("Remove").clicked()) {
+                palette.swatches.remove(index);
+            }
+            if ui.button("Save").clicked() {
+                let name = if palette.name.is_empty() {
+                    "palette"
+                } else {
+                    &palette.name
+                };
+                download_file(&format!("{}.ron", name), palette.to_ron().as_bytes());
+            }
+            if ui.button("Open").clicked() {
+                let extensions = Palette::EXTENSIONS.map(|extension| format!(".{}", extension));
+                let extensions: Vec<_> = extensions.iter().map(String::as_str).collect();
+                file_picker.open(&extensions);
+            }
+        });
+
+        ui.label("Registers");
+        ui.horizontal_wrapped(|ui| {
+            for (index, register) in state.registers.iter().enumerate() {
+                let key = EditorState::register_key(index);
+                let response = match register {
+                    Some(props) => swatch_button(ui, *props, props == material),
+                    None => ui.add_sized(SWATCH_SIZE, Button::new(key.to_string())),
+                };
+                if let (Some(props), true) = (register, response.clicked()) {
+                    *material = *props;
+                }
+                response.on_hover_text(format!("Key {}", key));
+            }
+        });
+        ui.label("Shift + a number key stores the material, the key alone loads it. Alt + click samples a voxel.");
+    });
+}
+
+/// Draws the name, position and parent of the active entity.
+fn draw_inspector(
+    ui: &mut Ui,
+    voxel_editor: &mut EditorResource,
+    query: &Query<(&Name, Option<&Children>, Option<&EntityBuffer>)>,
+    transforms: &Query<(&Transform, Option<&Parent>)>,
+) {
+    let entity = voxel_editor.entity;
+    let (name, ..) = ok_or_return!(query.get(entity));
+    let (transform, parent) = ok_or_return!(transforms.get(entity));
+
+    ui.horizontal(|ui| {
+        ui.label("Name");
+        let mut text = name.to_string();
+        let response = ui.text_edit_singleline(&mut text);
+        if response.changed() {
+            voxel_editor.edits.push(EditorEdit::Rename {
+                entity,
+                name: text,
+                merge: true,
+            });
+        }
+    });
+
+    ui.horizontal(|ui| {
+        ui.label("Position");
+        let mut translation = transform.translation;
+        let mut merge = false;
+        let mut changed = false;
+        for axis in 0..3 {
+            let response = ui.add(DragValue::new(&mut translation[axis]).speed(0.1));
+            changed |= response.changed();
+            // Dragging is recorded as a single move.
+            merge |= response.dragged() && !response.drag_started();
+        }
+
+        if changed {
+            voxel_editor.edits.push(EditorEdit::Move {
+                entity,
+                transform: Transform {
+                    translation,
+                    ..*transform
+                },
+                merge,
+            });
+        }
+    });
+
+    let parent = unwrap_or_return!(parent).get();
+    let mut candidates = vec![];
+    collect_parent_candidates(voxel_editor.prefab_entity, entity, query, &mut candidates);
+    let parent_name = query
+        .get(parent)
+        .map(|(name, ..)| name.to_string())
+        .unwrap_or_default();
+
+    ComboBox::from_label("Parent")
+        .selected_text(parent_name)
+        .show_ui(ui, |ui| {
+            for (candidate, name) in candidates {
+                if ui.selectable_label(candidate == parent, name).clicked() && candidate != parent {
+                    voxel_editor.edits.push(EditorEdit::Reparent {
+                        entity,
+                        parent: candidate,
+                    });
+                }
+            }
+        });
+}
+
+/// Collects the entities of the tree under `root` that `entity` could be moved under, which is
+/// every one but itself and its descendants.
+fn collect_parent_candidates(
+    root: Entity,
+    entity: Entity,
+    query: &Query<(&Name, Option<&Children>, Option<&EntityBuffer>)>,
+    candidates: &mut Vec<(Entity, String)>,
+) {
+    if root == entity {
+        return;
+    }
+
+    let (name, children, _) = ok_or_return!(query.get(root));
+    candidates.push((root, name.to_string()));
+    for child in children.into_iter().flat_map(|children| children.iter()) {
+        collect_parent_candidates(*child, entity, query, candidates);
+    }
+}
+
 fn draw_entity_tree(
     ui: &mut Ui,
     voxel_editor: &mut EditorResource,
     entity: Entity,
     query: &Query<(&Name, Option<&Children>, Option<&EntityBuffer>)>,
 ) {
-    let (name, children, entity_buffer) = query.get(entity).unwrap();
-    let name = format!(
+    // Entities without a name (like voxel chunk meshes) aren't part of the tree.
+    let (name, children, entity_buffer) = ok_or_return!(query.get(entity));
+    let label = format!(
         "{}{}",
         name.as_str(),
         if voxel_editor.entity == entity {
@@ -82,20 +409,39 @@ fn draw_entity_tree(
     );
 
     if let Some(children) = children {
-        CollapsingHeader::new(name)
+        CollapsingHeader::new(label)
             .default_open(true)
             .show(ui, |ui| {
-                if entity_buffer.is_some() && ui.button("Activate").clicked() {
-                    voxel_editor.entity = entity;
-                }
+                draw_entity_buttons(ui, voxel_editor, entity, name, entity_buffer.is_some());
                 for child in children.iter() {
                     draw_entity_tree(ui, voxel_editor, *child, query);
                 }
             });
     } else {
-        ui.label(name);
-        if entity_buffer.is_some() && ui.button("Activate").clicked() {
-            voxel_editor.entity = entity;
-        }
+        ui.label(label);
+        draw_entity_buttons(ui, voxel_editor, entity, name, entity_buffer.is_some());
     }
 }
+
+fn draw_entity_buttons(
+    ui: &mut Ui,
+    voxel_editor: &mut EditorResource,
+    entity: Entity,
+    name: &str,
+    has_voxels: bool,
+) {
+    ui.horizontal(|ui| {
+        if has_voxels && ui.button("Activate").clicked() {
+            voxel_editor.activate(entity, name);
+        }
+        if ui.button("Add child").clicked() {
+            voxel_editor.edits.push(EditorEdit::SpawnChild {
+                parent: entity,
+                material: voxel_editor.material,
+            });
+        }
+        if entity != voxel_editor.prefab_entity && ui.button("Delete").clicked() {
+            voxel_editor.edits.push(EditorEdit::Despawn { entity });
+        }
+    });
+}
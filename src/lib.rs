@@ -1,13 +1,16 @@
 use std::f32::consts::*;
 
-use bevy::prelude::*;
+use bevy::{prelude::*, render::view::VisibilitySystems};
 use bevy_egui::EguiPlugin;
 use bevy_prototype_debug_lines::{DebugLines, DebugLinesPlugin};
 use bevy_rapier3d::prelude::*;
 use camera::CameraPlugin;
 use editor::EditorPlugin;
 use resize::ResizePlugin;
-use voxel::VoxelMaterial;
+use voxel::{
+    count_drawn_chunks, cull_occluded_chunks, release_cached_volumes, VoxelChunkStats,
+    VoxelDetailTextures, VoxelMaterial, VoxelMeshCache, VoxelShadowPlugin,
+};
 
 #[macro_use]
 mod macros;
@@ -30,6 +33,16 @@ pub fn core_main() {
         .add_plugin(CameraPlugin)
         .add_startup_system(setup_world_and_camera)
         .add_plugin(MaterialPlugin::<VoxelMaterial>::default())
+        .add_plugin(VoxelShadowPlugin)
+        .init_resource::<VoxelDetailTextures>()
+        .init_resource::<VoxelChunkStats>()
+        .init_resource::<VoxelMeshCache>()
+        .add_system(cull_occluded_chunks)
+        .add_system_to_stage(CoreStage::PostUpdate, release_cached_volumes)
+        .add_system_to_stage(
+            CoreStage::PostUpdate,
+            count_drawn_chunks.after(VisibilitySystems::CheckVisibility),
+        )
         .add_system(draw_world_debug_lines)
         .run();
 }
@@ -1,6 +1,16 @@
+use std::{
+    collections::hash_map::DefaultHasher,
+    hash::{Hash, Hasher},
+    sync::OnceLock,
+};
+
 use bevy::prelude::*;
 
-use crate::voxel::{ChunkCoord, LocalCoord, PbrProps, WorldCoord, COUNT};
+use crate::voxel::{ChunkCoord, LocalCoord, OccupancyMask, PbrProps, WorldCoord, COUNT, WIDTH};
+
+/// How close (in voxels) to a chunk's face a change has to be to dirty the neighbor chunk on that
+/// side. Blocky meshing only looks at direct neighbors, but smooth meshing blurs over a wider area.
+const DIRTY_MARGIN: u32 = 3;
 
 /// An arbitrarily sized buffer of voxels, stored in 32x32x32 chunks. Chunks are stored in an
 /// immutable hashmap, meaning they are stored copy-on-write. This allows Buffers to be very cheaply
@@ -16,16 +26,31 @@ pub struct Buffer {
     /// expensive as the number of changes made. Chunks are GCed immediately when the non-default
     /// voxel count hits zero.
     pub chunks: im::HashMap<ChunkCoord, Chunk>,
+
+    /// Chunks touched by `set` since the last call to `take_dirty_chunks`. This includes neighbor
+    /// chunks when a voxel near the border changes, as their meshes depend on it.
+    pub dirty_chunks: im::HashSet<ChunkCoord>,
 }
 
 // 32*32*32*11 bytes = 360 KB chunks
 #[derive(Clone)]
 pub struct Chunk {
-    /// Voxels, linearized via `LocalCoord::linearize`.
+    /// Voxels, linearized via `LocalCoord::linearize`. Only change them through `set`, which keeps
+    /// the rest of the chunk in sync.
     pub voxels: Vec<PbrProps>,
 
     /// Count of non-empty (all zero) voxels. Used for compacting.
     pub count: usize,
+
+    /// Which voxels are non-empty, kept in sync by `set`.
+    pub occupied: OccupancyMask,
+
+    /// Which voxels are opaque, kept in sync by `set`.
+    pub opaque: OccupancyMask,
+
+    /// The hash of `voxels`, computed by `content_hash` the first time it's needed and reset by
+    /// `set`.
+    hash: OnceLock<u64>,
 }
 
 /// A facade to a Buffer for iterating voxels in world space. Reduces the number of hashmap lookups
@@ -55,6 +80,12 @@ impl Buffer {
         let coord: WorldCoord = c.into();
         let chunk_coord: ChunkCoord = coord.into();
 
+        if self.get(coord) == cell {
+            return;
+        }
+
+        self.mark_dirty(coord);
+
         let chunk = self
             .chunks
             .entry(chunk_coord)
@@ -67,6 +98,60 @@ impl Buffer {
         }
     }
 
+    /// Returns the set of dirty chunks, leaving the buffer with none.
+    pub fn take_dirty_chunks(&mut self) -> im::HashSet<ChunkCoord> {
+        std::mem::take(&mut self.dirty_chunks)
+    }
+
+    /// Returns every chunk that differs between the two buffers, along with all their neighbors.
+    /// This is much more expensive than dirty tracking and is meant for wholesale buffer swaps
+    /// (like undo).
+    pub fn changed_chunks(&self, other: &Buffer) -> im::HashSet<ChunkCoord> {
+        let mut changed = im::HashSet::new();
+
+        for chunk_coord in self.chunks.keys().chain(other.chunks.keys()) {
+            let same = match (self.chunks.get(chunk_coord), other.chunks.get(chunk_coord)) {
+                (Some(a), Some(b)) => a.voxels == b.voxels,
+                _ => false,
+            };
+
+            if !same {
+                for z in -1..=1 {
+                    for y in -1..=1 {
+                        for x in -1..=1 {
+                            changed.insert(ChunkCoord(chunk_coord.0 + IVec3::new(x, y, z)));
+                        }
+                    }
+                }
+            }
+        }
+
+        changed
+    }
+
+    /// Marks the chunk containing `coord` dirty, as well as any neighbor chunks it borders.
+    fn mark_dirty(&mut self, coord: WorldCoord) {
+        let chunk_coord: ChunkCoord = coord.into();
+        let local_coord: LocalCoord = coord.into();
+
+        // For each axis, the chunk offsets that can see this voxel (always 0, plus -1 or 1 if it
+        // sits near that face of the chunk).
+        let offsets = |v: u32| match v {
+            v if v < DIRTY_MARGIN => [0, -1],
+            v if v >= WIDTH as u32 - DIRTY_MARGIN => [0, 1],
+            _ => [0, 0],
+        };
+
+        for z in offsets(local_coord.0.z) {
+            for y in offsets(local_coord.0.y) {
+                for x in offsets(local_coord.0.x) {
+                    self.dirty_chunks
+                        .insert(ChunkCoord(chunk_coord.0 + IVec3::new(x, y, z)));
+                }
+            }
+        }
+    }
+
     pub fn count(&self) -> usize {
         self.chunks.values().map(|c| c.count).sum()
     }
@@ -116,14 +201,64 @@ impl Chunk {
         }
 
         self.voxels[idx] = mat;
+        self.occupied.set(coord, !mat.is_empty());
+        self.opaque.set(coord, mat.is_opaque());
+        self.hash.take();
+    }
+
+    /// A hash of the chunk's voxels. Only computed again after the chunk changes, so hashing a
+    /// whole buffer only costs as much as the chunks edited since it was last hashed.
+    pub fn content_hash(&self) -> u64 {
+        *self.hash.get_or_init(|| {
+            let mut hasher = DefaultHasher::new();
+            self.voxels.hash(&mut hasher);
+            hasher.finish()
+        })
+    }
+
+    /// Bytes of memory the chunk uses, including its voxels and masks.
+    pub fn memory_size(&self) -> usize {
+        std::mem::size_of::<Self>()
+            + self.voxels.capacity() * std::mem::size_of::<PbrProps>()
+            + self.occupied.memory_size()
+            + self.opaque.memory_size()
+    }
+
+    /// Builds a chunk from linearized voxels, computing its count and masks.
+    pub fn from_voxels(voxels: Vec<PbrProps>) -> Self {
+        let mut chunk = Self::default();
+
+        for (coord, props) in local_coords().zip(voxels.iter()) {
+            if !props.is_empty() {
+                chunk.count += 1;
+                chunk.occupied.set(coord, true);
+            }
+            if props.is_opaque() {
+                chunk.opaque.set(coord, true);
+            }
+        }
+
+        chunk.voxels = voxels;
+        chunk
     }
 }
 
+/// Every local coordinate of a chunk, in linearized order.
+fn local_coords() -> impl Iterator<Item = LocalCoord> {
+    let width = WIDTH as u32;
+    (0..width).flat_map(move |z| {
+        (0..width).flat_map(move |y| (0..width).map(move |x| LocalCoord(UVec3::new(x, y, z))))
+    })
+}
+
 impl Default for Chunk {
     fn default() -> Self {
         Self {
             voxels: vec![Default::default(); COUNT],
             count: Default::default(),
+            occupied: Default::default(),
+            opaque: Default::default(),
+            hash: Default::default(),
         }
     }
 }
@@ -152,4 +287,37 @@ impl<'a> FastBufferReader<'a> {
 
         self.chunk.map_or(default(), |c| c.get(local_coord))
     }
+
+    pub fn buffer(&self) -> &'a Buffer {
+        self.buffer
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_dirty_chunks() {
+        let mut buffer = Buffer::default();
+        let p = PbrProps {
+            metallic: 255,
+            ..Default::default()
+        };
+
+        // Interior voxels only dirty their own chunk.
+        buffer.set(WorldCoord(IVec3::new(4, 4, 4)), p);
+        assert_eq!(buffer.take_dirty_chunks().len(), 1);
+
+        // Setting the same value again is a no-op.
+        buffer.set(WorldCoord(IVec3::new(4, 4, 4)), p);
+        assert!(buffer.dirty_chunks.is_empty());
+
+        // A corner voxel borders 7 other chunks.
+        buffer.set(WorldCoord(IVec3::new(0, 0, 0)), p);
+        let dirty = buffer.take_dirty_chunks();
+        assert_eq!(dirty.len(), 8);
+        assert!(dirty.contains(&ChunkCoord(IVec3::new(-1, -1, -1))));
+        assert!(!dirty.contains(&ChunkCoord(IVec3::new(1, 0, 0))));
+    }
 }
@@ -0,0 +1,333 @@
+use std::collections::{HashMap, HashSet};
+
+use bevy::{prelude::*, render::primitives::Aabb};
+
+use super::{
+    mesh_chunk, mesh_chunk_smooth, packed_mesh_aabb, packed_mesh_materials, Buffer, CachedVolume,
+    ChunkCoord, MaterialTable, MeshLayer, VoxelDetailTextures, VoxelMaterial, WorldCoord,
+};
+
+const LAYERS: [MeshLayer; 2] = [MeshLayer::Opaque, MeshLayer::Translucent];
+
+/// Put on the child entities that render a single layer of a single chunk of their parent's voxel
+/// buffer.
+#[derive(Component, Debug, Clone, Copy)]
+pub struct VoxelChunk {
+    pub chunk_coord: ChunkCoord,
+    pub layer: MeshLayer,
+}
+
+/// How voxels are turned into a mesh.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum Mesher {
+    /// One quad per visible voxel face.
+    #[default]
+    Blocky,
+
+    /// Surface Nets, for rocks, terrain and other organic shapes.
+    Smooth,
+}
+
+/// Put on a voxel entity to render its buffer as child mesh entities per chunk (and per layer), so
+/// edits only need to remesh (and re-upload) the chunks they touched.
+#[derive(Component, Default)]
+pub struct VoxelChunks {
+    pub material: Handle<VoxelMaterial>,
+    pub translucent_material: Handle<VoxelMaterial>,
+    pub entities: HashMap<(ChunkCoord, MeshLayer), Entity>,
+
+    /// The mesh (and its bounds) of each of `entities`, so they can be shared through the
+    /// `VoxelMeshCache`.
+    pub meshes: HashMap<(ChunkCoord, MeshLayer), (Handle<Mesh>, Aabb)>,
+
+    /// Shared by both materials. Use `upload_material_table` after remeshing.
+    pub material_table: MaterialTable,
+
+    /// Use `set_mesher` to change this, so all chunks get remeshed.
+    pub mesher: Mesher,
+
+    /// Set when the mesher changes, as every chunk is then out of date.
+    pub needs_full_remesh: bool,
+
+    /// Hide chunks enclosed on all 6 sides by chunks full of opaque voxels, unless the camera is
+    /// inside them. Their insides can't be seen from anywhere else. See `cull_occluded_chunks`.
+    pub occlusion_culling: bool,
+
+    /// Chunks full of opaque voxels, updated as chunks are remeshed.
+    opaque_chunks: HashSet<ChunkCoord>,
+
+    /// Meshed chunks whose 6 neighbors are all in `opaque_chunks`.
+    enclosed_chunks: HashSet<ChunkCoord>,
+}
+
+impl VoxelChunks {
+    pub fn new(
+        materials: &mut Assets<VoxelMaterial>,
+        images: &mut Assets<Image>,
+        detail_textures: &VoxelDetailTextures,
+    ) -> Self {
+        let material_table = MaterialTable::new(images);
+        let mut add_material = |layer| {
+            materials.add(VoxelMaterial {
+                panel_normal_map: Some(detail_textures.panel.clone()),
+                bevel_normal_map: Some(detail_textures.bevel.clone()),
+                ..VoxelMaterial::for_layer(layer, material_table.image.clone())
+            })
+        };
+
+        Self {
+            material: add_material(MeshLayer::Opaque),
+            translucent_material: add_material(MeshLayer::Translucent),
+            entities: default(),
+            meshes: default(),
+            material_table,
+            mesher: default(),
+            needs_full_remesh: false,
+            occlusion_culling: false,
+            opaque_chunks: default(),
+            enclosed_chunks: default(),
+        }
+    }
+
+    pub fn set_mesher(&mut self, mesher: Mesher) {
+        if self.mesher != mesher {
+            self.mesher = mesher;
+            self.needs_full_remesh = true;
+        }
+    }
+
+    /// Rebuilds the meshes of the given chunks from `buffer`, spawning child entities for chunks
+    /// that are new and despawning the children of chunks that no longer have anything to draw.
+    pub fn remesh<I>(
+        &mut self,
+        commands: &mut Commands,
+        meshes: &mut Assets<Mesh>,
+        parent: Entity,
+        buffer: &Buffer,
+        chunks: I,
+    ) where
+        I: IntoIterator<Item = ChunkCoord>,
+    {
+        for chunk_coord in chunks {
+            self.update_opaque_chunk(buffer, chunk_coord);
+
+            for layer in LAYERS {
+                let key = (chunk_coord, layer);
+                let mesh = if !buffer.chunks.contains_key(&chunk_coord) {
+                    None
+                } else {
+                    match self.mesher {
+                        Mesher::Blocky => {
+                            mesh_chunk(buffer, chunk_coord, layer, &mut self.material_table)
+                        }
+                        Mesher::Smooth => mesh_chunk_smooth(buffer, chunk_coord, layer),
+                    }
+                };
+
+                match mesh {
+                    Some(mesh) => {
+                        let materials = packed_mesh_materials(&mesh);
+                        self.material_table.set_mesh_materials(key, materials);
+
+                        // Bevy only calculates the Aabb of entities that don't have one yet, so it
+                        // has to be kept up to date here as the chunk's mesh changes.
+                        let aabb = mesh
+                            .compute_aabb()
+                            .or_else(|| packed_mesh_aabb(&mesh))
+                            .unwrap_or_default();
+                        let mesh = meshes.add(mesh);
+                        self.set_chunk_mesh(commands, parent, key, mesh, aabb);
+                    }
+                    None => self.remove_chunk_mesh(commands, key),
+                }
+            }
+        }
+
+        self.update_enclosed_chunks();
+    }
+
+    /// Replaces every chunk mesh with those of a `VoxelMeshCache` entry of `buffer`, instead of
+    /// meshing it again.
+    pub fn adopt(
+        &mut self,
+        commands: &mut Commands,
+        parent: Entity,
+        buffer: &Buffer,
+        cached: &CachedVolume,
+    ) {
+        let stale: Vec<_> = self
+            .entities
+            .keys()
+            .filter(|key| !cached.meshes.contains_key(key))
+            .copied()
+            .collect();
+        for key in stale {
+            self.remove_chunk_mesh(commands, key);
+        }
+
+        for (key, (mesh, aabb)) in cached.meshes.iter() {
+            self.set_chunk_mesh(commands, parent, *key, mesh.clone(), aabb.clone());
+        }
+
+        // Vertices index into the table the cached meshes were built with.
+        self.material_table.copy_from(&cached.material_table);
+
+        self.opaque_chunks.clear();
+        for chunk_coord in buffer.chunks.keys() {
+            self.update_opaque_chunk(buffer, *chunk_coord);
+        }
+        self.update_enclosed_chunks();
+    }
+
+    /// Points the child entity of the chunk's layer at the mesh, spawning it if it's new.
+    fn set_chunk_mesh(
+        &mut self,
+        commands: &mut Commands,
+        parent: Entity,
+        key: (ChunkCoord, MeshLayer),
+        mesh: Handle<Mesh>,
+        aabb: Aabb,
+    ) {
+        self.meshes.insert(key, (mesh.clone(), aabb.clone()));
+
+        if let Some(entity) = self.entities.get(&key) {
+            commands.entity(*entity).insert((mesh, aabb));
+            return;
+        }
+
+        let (chunk_coord, layer) = key;
+        let material = match layer {
+            MeshLayer::Opaque => self.material.clone(),
+            MeshLayer::Translucent => self.translucent_material.clone(),
+        };
+
+        let entity = commands
+            .spawn((
+                VoxelChunk { chunk_coord, layer },
+                aabb,
+                MaterialMeshBundle {
+                    mesh,
+                    material,
+                    transform: Transform::from_translation(
+                        layer.chunk_origin(chunk_coord).as_vec3(),
+                    ),
+                    ..default()
+                },
+            ))
+            .id();
+
+        commands.entity(parent).add_child(entity);
+        self.entities.insert(key, entity);
+    }
+
+    /// Despawns the child entity of the chunk's layer, if it has one.
+    fn remove_chunk_mesh(&mut self, commands: &mut Commands, key: (ChunkCoord, MeshLayer)) {
+        self.meshes.remove(&key);
+        self.material_table.set_mesh_materials(key, vec![]);
+        if let Some(entity) = self.entities.remove(&key) {
+            commands.entity(entity).despawn_recursive();
+        }
+    }
+
+    fn update_opaque_chunk(&mut self, buffer: &Buffer, chunk_coord: ChunkCoord) {
+        let opaque = buffer
+            .chunks
+            .get(&chunk_coord)
+            .map_or(false, |chunk| chunk.opaque.is_full());
+        if opaque {
+            self.opaque_chunks.insert(chunk_coord);
+        } else {
+            self.opaque_chunks.remove(&chunk_coord);
+        }
+    }
+
+    /// Changing a chunk can enclose (or expose) any of its neighbors, so this just redoes them all.
+    fn update_enclosed_chunks(&mut self) {
+        self.enclosed_chunks = self
+            .entities
+            .keys()
+            .map(|(chunk_coord, _)| *chunk_coord)
+            .filter(|chunk_coord| {
+                [
+                    IVec3::X,
+                    IVec3::NEG_X,
+                    IVec3::Y,
+                    IVec3::NEG_Y,
+                    IVec3::Z,
+                    IVec3::NEG_Z,
+                ]
+                .iter()
+                .all(|n| self.opaque_chunks.contains(&ChunkCoord(chunk_coord.0 + *n)))
+            })
+            .collect();
+    }
+
+    /// Uploads materials added to the table by `remesh`, if any.
+    pub fn upload_material_table(
+        &mut self,
+        images: &mut Assets<Image>,
+        materials: &mut Assets<VoxelMaterial>,
+    ) {
+        if self.material_table.upload(images) {
+            // Touch the materials so their bind groups pick up the new texture.
+            materials.get_mut(&self.material);
+            materials.get_mut(&self.translucent_material);
+        }
+    }
+}
+
+/// Counts of voxel chunk meshes across all voxel entities, as of the last frame.
+#[derive(Resource, Default, Debug)]
+pub struct VoxelChunkStats {
+    pub total: usize,
+    /// Hidden by `VoxelChunks::occlusion_culling`.
+    pub occluded: usize,
+    /// Visible after occlusion and frustum culling.
+    pub drawn: usize,
+}
+
+/// Hides the enclosed chunks of voxel entities with `VoxelChunks::occlusion_culling`, unless the
+/// camera is inside the chunk.
+pub fn cull_occluded_chunks(
+    cameras: Query<&GlobalTransform, With<Camera3d>>,
+    voxel_entities: Query<(&VoxelChunks, &GlobalTransform)>,
+    mut chunks: Query<&mut Visibility, With<VoxelChunk>>,
+) {
+    let camera = unwrap_or_return!(cameras.iter().next()).translation();
+
+    for (voxel_chunks, transform) in voxel_entities.iter() {
+        let camera_coord = transform.affine().inverse().transform_point3(camera);
+        let camera_chunk = ChunkCoord::from(WorldCoord(camera_coord.floor().as_ivec3()));
+
+        for ((chunk_coord, _), entity) in voxel_chunks.entities.iter() {
+            let occluded = voxel_chunks.occlusion_culling
+                && *chunk_coord != camera_chunk
+                && voxel_chunks.enclosed_chunks.contains(chunk_coord);
+
+            if let Ok(mut visibility) = chunks.get_mut(*entity) {
+                // Only write on change, so Bevy's change detection doesn't fire every frame.
+                if visibility.is_visible == occluded {
+                    visibility.is_visible = !occluded;
+                }
+            }
+        }
+    }
+}
+
+/// Updates `VoxelChunkStats`. Must run after Bevy's visibility checks.
+pub fn count_drawn_chunks(
+    mut stats: ResMut<VoxelChunkStats>,
+    chunks: Query<(&Visibility, &ComputedVisibility), With<VoxelChunk>>,
+) {
+    *stats = default();
+
+    for (visibility, computed_visibility) in chunks.iter() {
+        stats.total += 1;
+        if !visibility.is_visible {
+            stats.occluded += 1;
+        }
+        if computed_visibility.is_visible() {
+            stats.drawn += 1;
+        }
+    }
+}
@@ -0,0 +1,267 @@
+use std::collections::HashMap;
+
+use bevy::prelude::*;
+use bevy_rapier3d::prelude::*;
+
+use super::{
+    Buffer, CachedColliders, Chunk, ChunkCoord, FastBufferReader, LocalCoord, WorldCoord,
+    NORM_TAN_BITAN, WIDTH,
+};
+
+/// How voxel colliders are built.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum VoxelColliderMode {
+    /// A compound of cuboids, greedily merged from solid voxels. Cheap to collide against, and
+    /// solid all the way through so fast objects don't tunnel.
+    #[default]
+    Cuboids,
+
+    /// A triangle mesh of the exposed voxel faces. Exact, but hollow.
+    TriMesh,
+}
+
+/// Put on the child entities that hold the collider of a single chunk of their parent's buffer.
+#[derive(Component, Debug, Clone, Copy)]
+pub struct VoxelChunkCollider(pub ChunkCoord);
+
+/// Put on a voxel entity to give its buffer collision, as one child collider entity per chunk.
+/// Children follow the entity's transform like any other child.
+#[derive(Component, Default)]
+pub struct VoxelColliders {
+    /// Use `set_mode` to change this, so all chunks get rebuilt.
+    pub mode: VoxelColliderMode,
+    pub entities: HashMap<ChunkCoord, Entity>,
+
+    /// The collider of each of `entities`, so they can be shared through the `VoxelMeshCache`.
+    /// Colliders are reference counted, so clones share the same shape.
+    pub colliders: HashMap<ChunkCoord, Collider>,
+
+    /// Set when the mode changes, as every chunk is then out of date.
+    pub needs_full_rebuild: bool,
+}
+
+impl VoxelColliders {
+    pub fn set_mode(&mut self, mode: VoxelColliderMode) {
+        if self.mode != mode {
+            self.mode = mode;
+            self.needs_full_rebuild = true;
+        }
+    }
+
+    /// Rebuilds the colliders of the given chunks from `buffer`, spawning child entities for chunks
+    /// that are new and despawning the children of chunks that are now empty.
+    pub fn rebuild<I>(
+        &mut self,
+        commands: &mut Commands,
+        parent: Entity,
+        buffer: &Buffer,
+        chunks: I,
+    ) where
+        I: IntoIterator<Item = ChunkCoord>,
+    {
+        for chunk_coord in chunks {
+            let collider = match self.mode {
+                VoxelColliderMode::Cuboids => chunk_cuboids_collider(buffer, chunk_coord),
+                VoxelColliderMode::TriMesh => chunk_trimesh_collider(buffer, chunk_coord),
+            };
+
+            match collider {
+                Some(collider) => self.set_chunk_collider(commands, parent, chunk_coord, collider),
+                None => self.remove_chunk_collider(commands, chunk_coord),
+            }
+        }
+    }
+
+    /// Replaces every chunk collider with those of a `VoxelMeshCache` entry, instead of building
+    /// them again.
+    pub fn adopt(&mut self, commands: &mut Commands, parent: Entity, cached: &CachedColliders) {
+        let stale: Vec<_> = self
+            .entities
+            .keys()
+            .filter(|chunk_coord| !cached.colliders.contains_key(chunk_coord))
+            .copied()
+            .collect();
+        for chunk_coord in stale {
+            self.remove_chunk_collider(commands, chunk_coord);
+        }
+
+        for (chunk_coord, collider) in cached.colliders.iter() {
+            self.set_chunk_collider(commands, parent, *chunk_coord, collider.clone());
+        }
+    }
+
+    /// Gives the child entity of the chunk the collider, spawning it if it's new.
+    fn set_chunk_collider(
+        &mut self,
+        commands: &mut Commands,
+        parent: Entity,
+        chunk_coord: ChunkCoord,
+        collider: Collider,
+    ) {
+        self.colliders.insert(chunk_coord, collider.clone());
+
+        if let Some(entity) = self.entities.get(&chunk_coord) {
+            commands.entity(*entity).insert(collider);
+            return;
+        }
+
+        let entity = commands
+            .spawn((
+                VoxelChunkCollider(chunk_coord),
+                collider,
+                TransformBundle::from(Transform::from_translation(
+                    chunk_coord.first_cell_coord().0.as_vec3(),
+                )),
+            ))
+            .id();
+
+        commands.entity(parent).add_child(entity);
+        self.entities.insert(chunk_coord, entity);
+    }
+
+    /// Despawns the child entity of the chunk, if it has one.
+    fn remove_chunk_collider(&mut self, commands: &mut Commands, chunk_coord: ChunkCoord) {
+        self.colliders.remove(&chunk_coord);
+        if let Some(entity) = self.entities.remove(&chunk_coord) {
+            commands.entity(entity).despawn_recursive();
+        }
+    }
+}
+
+/// Builds a compound collider of the chunk's voxels, relative to the chunk's first cell.
+pub fn chunk_cuboids_collider(buffer: &Buffer, chunk_coord: ChunkCoord) -> Option<Collider> {
+    let shapes: Vec<_> = greedy_boxes(buffer.chunks.get(&chunk_coord)?)
+        .into_iter()
+        .map(|(min, size)| {
+            let half = size.as_vec3() / 2.0;
+            (
+                min.as_vec3() + half,
+                Quat::IDENTITY,
+                Collider::cuboid(half.x, half.y, half.z),
+            )
+        })
+        .collect();
+
+    if shapes.is_empty() {
+        None
+    } else {
+        Some(Collider::compound(shapes))
+    }
+}
+
+/// Greedily merges the solid voxels of a chunk into boxes, first along X, then Y, then Z. Returns
+/// tuples of (min, size) in chunk-local coordinates.
+fn greedy_boxes(chunk: &Chunk) -> Vec<(UVec3, UVec3)> {
+    let w = WIDTH as u32;
+    let idx = |p: UVec3| LocalCoord(p).linearize();
+
+    // Solid voxels that aren't part of a box yet.
+    let mut open: Vec<bool> = chunk.voxels.iter().map(|v| !v.is_empty()).collect();
+    let mut boxes = vec![];
+
+    for z in 0..w {
+        for y in 0..w {
+            for x in 0..w {
+                if !open[idx(UVec3::new(x, y, z))] {
+                    continue;
+                }
+
+                let mut size = UVec3::ONE;
+
+                while x + size.x < w && open[idx(UVec3::new(x + size.x, y, z))] {
+                    size.x += 1;
+                }
+
+                while y + size.y < w
+                    && (x..x + size.x).all(|x| open[idx(UVec3::new(x, y + size.y, z))])
+                {
+                    size.y += 1;
+                }
+
+                while z + size.z < w
+                    && (y..y + size.y)
+                        .all(|y| (x..x + size.x).all(|x| open[idx(UVec3::new(x, y, z + size.z))]))
+                {
+                    size.z += 1;
+                }
+
+                for bz in z..z + size.z {
+                    for by in y..y + size.y {
+                        for bx in x..x + size.x {
+                            open[idx(UVec3::new(bx, by, bz))] = false;
+                        }
+                    }
+                }
+
+                boxes.push((UVec3::new(x, y, z), size));
+            }
+        }
+    }
+
+    boxes
+}
+
+/// Builds a triangle mesh collider of the chunk's exposed voxel faces, relative to the chunk's
+/// first cell.
+pub fn chunk_trimesh_collider(buffer: &Buffer, chunk_coord: ChunkCoord) -> Option<Collider> {
+    buffer.chunks.get(&chunk_coord)?;
+
+    let origin = chunk_coord.first_cell_coord().0;
+    let mut reader = FastBufferReader::new(buffer);
+    let mut vertices = vec![];
+    let mut indices = vec![];
+
+    for WorldCoord(coord) in chunk_coord.iter_world_coords() {
+        if reader.get(WorldCoord(coord)).is_empty() {
+            continue;
+        }
+
+        for (quad_origin, norm, tan, bi_tan) in NORM_TAN_BITAN {
+            if !reader.get(WorldCoord(coord + norm)).is_empty() {
+                continue;
+            }
+
+            let p = coord + quad_origin - origin;
+            let base = vertices.len() as u32;
+            vertices.extend([p, p + tan, p + tan + bi_tan, p + bi_tan].map(|v| v.as_vec3()));
+            indices.extend([[base, base + 1, base + 2], [base, base + 2, base + 3]]);
+        }
+    }
+
+    if indices.is_empty() {
+        None
+    } else {
+        Some(Collider::trimesh(vertices, indices))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::voxel::{PbrProps, Rgba};
+
+    use super::*;
+
+    #[test]
+    fn test_greedy_boxes() {
+        let mut buffer = Buffer::default();
+        let p = PbrProps {
+            color: Rgba::from(Color::RED),
+            ..default()
+        };
+
+        // A 3x2x2 block, plus one voxel sticking out the top of it.
+        for coord in WorldCoord::iter_range((0, 0, 0).into(), (2, 1, 1).into()) {
+            buffer.set(coord, p);
+        }
+        buffer.set(WorldCoord::from((0, 2, 0)), p);
+
+        let boxes = greedy_boxes(buffer.chunks.get(&ChunkCoord(IVec3::ZERO)).unwrap());
+        assert_eq!(
+            boxes,
+            vec![
+                (UVec3::new(0, 0, 0), UVec3::new(3, 2, 2)),
+                (UVec3::new(0, 2, 0), UVec3::new(1, 1, 1)),
+            ]
+        );
+    }
+}
@@ -3,7 +3,7 @@ use std::collections::HashMap;
 use bevy::prelude::*;
 use serde::{Deserialize, Serialize};
 
-use super::{Buffer, Chunk, ChunkCoord, PbrProps};
+use super::{Buffer, Chunk, ChunkCoord, PbrProps, COUNT};
 
 /// Analogous to a `Buffer` but stored chunk data in Run Length Encoded format.
 ///
@@ -65,21 +65,14 @@ impl From<&Buffer> for CompressedBuffer {
 
 impl From<&CompressedChunk> for Chunk {
     fn from(compressed_chunk: &CompressedChunk) -> Self {
-        let mut chunk = Self::default();
-        let mut i = 0;
+        let mut voxels = Vec::with_capacity(COUNT);
 
         for run in &compressed_chunk.runs {
-            if run.pbr_props != PbrProps::default() {
-                chunk.count += run.len as usize;
-            }
-
-            for _ in 0..run.len {
-                chunk.voxels[i] = run.pbr_props;
-                i += 1;
-            }
+            voxels.extend(std::iter::repeat(run.pbr_props).take(run.len as usize));
         }
+        voxels.resize(COUNT, PbrProps::default());
 
-        chunk
+        Chunk::from_voxels(voxels)
     }
 }
 
@@ -91,6 +84,7 @@ impl From<&CompressedBuffer> for Buffer {
                 .iter()
                 .map(|(coord, compressed_chunk)| (ChunkCoord(*coord), compressed_chunk.into()))
                 .collect(),
+            dirty_chunks: Default::default(),
         }
     }
 }
@@ -0,0 +1,213 @@
+use std::collections::{HashMap, VecDeque};
+
+use bevy::prelude::*;
+
+use super::{Buffer, ChunkCoord, PbrProps, WorldCoord};
+
+/// Emissive voxels only join a cluster if they are within this many voxels (on every axis) of the
+/// cluster's first voxel. This splits up large glowing surfaces into several lights instead of one
+/// light in the middle of them.
+const CLUSTER_RADIUS: i32 = 4;
+
+/// Light output of a single voxel at full emission. Matches Bevy's default `PointLight`.
+const LUMENS_PER_VOXEL: f32 = 800.0;
+
+/// A group of adjacent emissive voxels, lit by a single point light.
+#[derive(Debug, Clone)]
+pub struct EmissiveCluster {
+    /// Emission weighted center of the cluster, in the local space of the buffer.
+    pub center: Vec3,
+    /// Emission weighted average color of the voxels in the cluster.
+    pub color: Color,
+    /// Total light output of the cluster, in lumens.
+    pub intensity: f32,
+    pub voxel_count: usize,
+}
+
+/// Put on voxel entities to have their emissive voxels light the area around them, via child
+/// point lights generated from clusters of emissive voxels.
+#[derive(Component)]
+pub struct VoxelLights {
+    /// Only the brightest clusters get a light.
+    pub max_lights: usize,
+
+    /// Emissive voxels of each chunk, cached so only changed chunks need rescanning.
+    emissive_voxels: HashMap<ChunkCoord, Vec<(IVec3, PbrProps)>>,
+
+    lights: Vec<Entity>,
+}
+
+impl Default for VoxelLights {
+    fn default() -> Self {
+        Self {
+            max_lights: 8,
+            emissive_voxels: default(),
+            lights: default(),
+        }
+    }
+}
+
+impl VoxelLights {
+    /// Rescans the given chunks for emissive voxels and replaces the child lights of `parent` with
+    /// ones generated from the new clusters.
+    pub fn update<I>(&mut self, commands: &mut Commands, parent: Entity, buffer: &Buffer, chunks: I)
+    where
+        I: IntoIterator<Item = ChunkCoord>,
+    {
+        let mut changed = false;
+
+        for chunk_coord in chunks {
+            let voxels = chunk_emissive_voxels(buffer, chunk_coord);
+            let previous = if voxels.is_empty() {
+                self.emissive_voxels.remove(&chunk_coord)
+            } else {
+                self.emissive_voxels.insert(chunk_coord, voxels.clone())
+            };
+
+            changed |= previous.unwrap_or_default() != voxels;
+        }
+
+        if !changed {
+            return;
+        }
+
+        for light in self.lights.drain(..) {
+            commands.entity(light).despawn_recursive();
+        }
+
+        let mut clusters =
+            cluster_emissive_voxels(self.emissive_voxels.values().flatten().copied().collect());
+        clusters.sort_by(|a, b| b.intensity.total_cmp(&a.intensity));
+        clusters.truncate(self.max_lights);
+
+        for cluster in clusters {
+            let light = commands
+                .spawn(PointLightBundle {
+                    point_light: PointLight {
+                        color: cluster.color,
+                        intensity: cluster.intensity,
+                        // Range grows with the square root of intensity, as falloff is quadratic.
+                        range: 20.0 * (cluster.intensity / LUMENS_PER_VOXEL).sqrt(),
+                        ..default()
+                    },
+                    transform: Transform::from_translation(cluster.center),
+                    ..default()
+                })
+                .id();
+
+            commands.entity(parent).add_child(light);
+            self.lights.push(light);
+        }
+    }
+}
+
+fn chunk_emissive_voxels(buffer: &Buffer, chunk_coord: ChunkCoord) -> Vec<(IVec3, PbrProps)> {
+    let chunk = match buffer.chunks.get(&chunk_coord) {
+        Some(chunk) => chunk,
+        None => return vec![],
+    };
+
+    chunk_coord
+        .iter_world_coords()
+        .filter_map(|coord| {
+            let props = chunk.get(coord);
+            (props.emission > 0).then_some((coord.0, props))
+        })
+        .collect()
+}
+
+/// Flood fills (26-connected) emissive voxels into clusters, bounded by `CLUSTER_RADIUS`.
+fn cluster_emissive_voxels(voxels: HashMap<IVec3, PbrProps>) -> Vec<EmissiveCluster> {
+    let mut remaining = voxels;
+    let mut clusters = vec![];
+
+    // Seed in a stable order, so the same buffer always produces the same lights.
+    let mut seeds: Vec<_> = remaining.keys().copied().collect();
+    seeds.sort_by_key(|c| (c.z, c.y, c.x));
+
+    for seed in seeds {
+        if !remaining.contains_key(&seed) {
+            continue;
+        }
+
+        let mut weight = 0.0;
+        let mut center = Vec3::ZERO;
+        let mut color = Vec3::ZERO;
+        let mut voxel_count = 0;
+
+        let mut queue = VecDeque::from([seed]);
+        while let Some(coord) = queue.pop_front() {
+            let props = match remaining.remove(&coord) {
+                Some(props) => props,
+                None => continue,
+            };
+
+            let emission = props.emission as f32 / u8::MAX as f32;
+            let [r, g, b, _] = Color::from(props.color).as_rgba_f32();
+
+            weight += emission;
+            center += (coord.as_vec3() + Vec3::splat(0.5)) * emission;
+            color += Vec3::new(r, g, b) * emission;
+            voxel_count += 1;
+
+            for neighbor in WorldCoord::iter_range(
+                WorldCoord(coord - IVec3::ONE),
+                WorldCoord(coord + IVec3::ONE),
+            ) {
+                if (neighbor.0 - seed).abs().max_element() <= CLUSTER_RADIUS
+                    && remaining.contains_key(&neighbor.0)
+                {
+                    queue.push_back(neighbor.0);
+                }
+            }
+        }
+
+        let color = color / weight;
+        clusters.push(EmissiveCluster {
+            center: center / weight,
+            color: Color::rgb(color.x, color.y, color.z),
+            intensity: weight * LUMENS_PER_VOXEL,
+            voxel_count,
+        });
+    }
+
+    clusters
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::voxel::Rgba;
+
+    use super::*;
+
+    #[test]
+    fn test_emissive_clusters() {
+        let mut buffer = Buffer::default();
+        let lamp = PbrProps {
+            color: Rgba::from(Color::WHITE),
+            emission: 255,
+            ..default()
+        };
+
+        // Two adjacent voxels (across a chunk boundary) and one on its own.
+        buffer.set(WorldCoord(IVec3::new(-1, 0, 0)), lamp);
+        buffer.set(WorldCoord(IVec3::new(0, 0, 0)), lamp);
+        buffer.set(WorldCoord(IVec3::new(10, 0, 0)), lamp);
+
+        let mut clusters = cluster_emissive_voxels(
+            buffer
+                .chunks
+                .keys()
+                .flat_map(|chunk_coord| chunk_emissive_voxels(&buffer, *chunk_coord))
+                .collect(),
+        );
+        clusters.sort_by_key(|c| c.voxel_count);
+
+        assert_eq!(clusters.len(), 2);
+        assert_eq!(clusters[0].voxel_count, 1);
+        assert_eq!(clusters[0].center, Vec3::new(10.5, 0.5, 0.5));
+        assert_eq!(clusters[1].voxel_count, 2);
+        assert_eq!(clusters[1].center, Vec3::new(0.0, 0.5, 0.5));
+        assert_eq!(clusters[1].intensity, 2.0 * LUMENS_PER_VOXEL);
+    }
+}
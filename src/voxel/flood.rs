@@ -0,0 +1,137 @@
+use std::collections::HashSet;
+
+use bevy::prelude::*;
+
+use super::{Buffer, PbrProps, WorldCoord};
+
+/// The 6 face neighbors of a voxel.
+pub const FACE_NEIGHBORS: [IVec3; 6] = [
+    IVec3::X,
+    IVec3::NEG_X,
+    IVec3::Y,
+    IVec3::NEG_Y,
+    IVec3::Z,
+    IVec3::NEG_Z,
+];
+
+/// Collects the voxels connected to `start` through `neighbors` for which `include` holds, starting
+/// with `start` itself. Returns nothing if `start` isn't included.
+pub fn flood_fill<F>(
+    buffer: &Buffer,
+    start: WorldCoord,
+    neighbors: &[IVec3],
+    mut include: F,
+) -> Vec<WorldCoord>
+where
+    F: FnMut(WorldCoord, PbrProps) -> bool,
+{
+    let mut filled = vec![];
+    let mut visited = HashSet::from([start]);
+    let mut stack = vec![start];
+
+    while let Some(coord) = stack.pop() {
+        if !include(coord, buffer.get(coord)) {
+            continue;
+        }
+
+        filled.push(coord);
+        for neighbor in neighbors {
+            let next = WorldCoord(coord.0 + *neighbor);
+            if visited.insert(next) {
+                stack.push(next);
+            }
+        }
+    }
+
+    filled
+}
+
+/// The connected region of voxel faces facing `normal` that share the plane, and the material, of
+/// the face of `start`: the faces a face extrusion from `start` moves. Only faces that aren't
+/// covered by a neighbor count.
+pub fn coplanar_faces(buffer: &Buffer, start: WorldCoord, normal: IVec3) -> Vec<WorldCoord> {
+    let props = buffer.get(start);
+    if props.is_empty() {
+        return vec![];
+    }
+
+    let neighbors: Vec<_> = FACE_NEIGHBORS
+        .into_iter()
+        .filter(|neighbor| neighbor.dot(normal) == 0)
+        .collect();
+
+    flood_fill(buffer, start, &neighbors, |coord, p| {
+        p == props && buffer.get(WorldCoord(coord.0 + normal)).is_empty()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::voxel::Rgba;
+
+    use super::*;
+
+    #[test]
+    fn test_coplanar_faces() {
+        let red = PbrProps {
+            color: Rgba::from(Color::RED),
+            ..default()
+        };
+        let blue = PbrProps {
+            color: Rgba::from(Color::BLUE),
+            ..default()
+        };
+
+        // A 4x4 red floor with one blue tile, and a red voxel covering another tile.
+        let mut buffer = Buffer::default();
+        for coord in
+            WorldCoord::iter_range(WorldCoord(IVec3::ZERO), WorldCoord(IVec3::new(3, 0, 3)))
+        {
+            buffer.set(coord, red);
+        }
+        buffer.set(WorldCoord(IVec3::new(3, 0, 3)), blue);
+        buffer.set(WorldCoord(IVec3::new(0, 1, 0)), red);
+
+        let faces = coplanar_faces(&buffer, WorldCoord(IVec3::new(1, 0, 1)), IVec3::Y);
+        assert_eq!(faces.len(), 14);
+        assert!(!faces.contains(&WorldCoord(IVec3::new(3, 0, 3))));
+        assert!(!faces.contains(&WorldCoord(IVec3::new(0, 0, 0))));
+
+        // The side of the floor continues up the voxel on top of it.
+        let faces = coplanar_faces(&buffer, WorldCoord(IVec3::new(1, 0, 0)), IVec3::NEG_Z);
+        assert_eq!(faces.len(), 5);
+
+        assert!(coplanar_faces(&buffer, WorldCoord(IVec3::new(9, 9, 9)), IVec3::Y).is_empty());
+    }
+
+    #[test]
+    fn test_flood_fill_tolerance() {
+        let shade = |r: u8| PbrProps {
+            color: Rgba {
+                r,
+                g: 0,
+                b: 0,
+                a: 255,
+            },
+            ..default()
+        };
+
+        // A row of slowly darkening reds, broken by a gap.
+        let mut buffer = Buffer::default();
+        for x in 0..8 {
+            buffer.set(WorldCoord(IVec3::new(x, 0, 0)), shade(200 - x as u8 * 4));
+        }
+        buffer.set(WorldCoord(IVec3::new(4, 0, 0)), default());
+
+        let seed = shade(200);
+        let fill = |tolerance: u8| {
+            flood_fill(&buffer, WorldCoord(IVec3::ZERO), &FACE_NEIGHBORS, |_, p| {
+                !p.is_empty() && p.difference(&seed) <= tolerance
+            })
+        };
+
+        assert_eq!(fill(0).len(), 1);
+        assert_eq!(fill(8).len(), 3);
+        assert_eq!(fill(255).len(), 4);
+    }
+}
@@ -0,0 +1,218 @@
+use std::collections::HashMap;
+
+use bevy::{
+    prelude::*,
+    render::render_resource::{Extent3d, TextureDimension, TextureFormat},
+};
+
+use super::{ChunkCoord, MeshLayer, PbrProps};
+
+/// Width of the material table texture, in texels. Must match `voxel_vert.wgsl`.
+pub const MATERIAL_TABLE_WIDTH: u32 = 512;
+
+/// The distinct PbrProps used by the blocky meshes of a voxel entity, ignoring their
+/// `DetailPattern`, so vertices only carry an index into it. Uploaded as an `Rgba8Unorm` texture
+/// with two texels per material (color and opacity, then metallic, roughness, reflectance and
+/// emission), as WebGL2 has no storage buffers.
+///
+/// Chunk meshes register the materials they use with `set_mesh_materials`. A material no mesh uses
+/// any more frees its slot for the next new material, so indexes in existing meshes stay valid and
+/// the table only grows to the most materials in use at once.
+#[derive(Default, Clone)]
+pub struct MaterialTable {
+    pub image: Handle<Image>,
+    props: Vec<PbrProps>,
+    indexes: HashMap<PbrProps, u32>,
+
+    /// How many chunk meshes use each slot of `props`.
+    users: Vec<u32>,
+
+    /// Slots no mesh uses, reused before the table grows.
+    free: Vec<u32>,
+
+    /// The slots each chunk mesh uses.
+    meshes: HashMap<(ChunkCoord, MeshLayer), Vec<u32>>,
+
+    /// Set when the table changed since it was last uploaded.
+    changed: bool,
+}
+
+impl MaterialTable {
+    pub fn new(images: &mut Assets<Image>) -> Self {
+        let mut table = Self::default();
+        table.image = images.add(table.to_image());
+        table
+    }
+
+    /// The slot of the material, adding it if it's new. Meshes using the slot have to be registered
+    /// with `set_mesh_materials`, which frees it once none do.
+    pub fn index_of(&mut self, props: PbrProps) -> u32 {
+        // Patterns are packed into the vertices, so materials differing only by pattern share a
+        // slot.
+        let props = PbrProps {
+            pattern: default(),
+            ..props
+        };
+        if let Some(index) = self.indexes.get(&props) {
+            return *index;
+        }
+
+        let index = match self.free.pop() {
+            Some(index) => {
+                self.props[index as usize] = props;
+                index
+            }
+            None => {
+                self.props.push(props);
+                self.users.push(0);
+                (self.props.len() - 1) as u32
+            }
+        };
+        self.indexes.insert(props, index);
+        self.changed = true;
+        index
+    }
+
+    /// Records the slots a chunk mesh uses, releasing those of the mesh it replaces. An empty list
+    /// removes the mesh.
+    pub fn set_mesh_materials(&mut self, key: (ChunkCoord, MeshLayer), indexes: Vec<u32>) {
+        for index in &indexes {
+            self.users[*index as usize] += 1;
+        }
+
+        let old = if indexes.is_empty() {
+            self.meshes.remove(&key)
+        } else {
+            self.meshes.insert(key, indexes)
+        };
+
+        for index in old.into_iter().flatten() {
+            let users = &mut self.users[index as usize];
+            *users -= 1;
+            if *users == 0 {
+                self.indexes.remove(&self.props[index as usize]);
+                self.free.push(index);
+            }
+        }
+    }
+
+    /// Replaces the materials with those of another table, keeping this table's image.
+    pub fn copy_from(&mut self, other: &MaterialTable) {
+        self.props = other.props.clone();
+        self.indexes = other.indexes.clone();
+        self.users = other.users.clone();
+        self.free = other.free.clone();
+        self.meshes = other.meshes.clone();
+        self.changed = true;
+    }
+
+    /// Writes the table to its image if it changed. Returns whether it did, in which case the
+    /// materials using the table need to be marked as changed too, so their bind groups are
+    /// recreated with the new texture.
+    pub fn upload(&mut self, images: &mut Assets<Image>) -> bool {
+        if !self.changed {
+            return false;
+        }
+
+        self.changed = false;
+        if let Some(image) = images.get_mut(&self.image) {
+            *image = self.to_image();
+        }
+
+        true
+    }
+
+    fn to_image(&self) -> Image {
+        let texels = self.props.len() as u32 * 2;
+        let height = ((texels + MATERIAL_TABLE_WIDTH - 1) / MATERIAL_TABLE_WIDTH).max(1);
+
+        let mut data = Vec::with_capacity((MATERIAL_TABLE_WIDTH * height * 4) as usize);
+        for p in &self.props {
+            let c = p.color;
+            data.extend([c.r, c.g, c.b, c.a]);
+            data.extend([p.metallic, p.roughness, p.reflectance, p.emission]);
+        }
+        data.resize((MATERIAL_TABLE_WIDTH * height * 4) as usize, 0);
+
+        Image::new(
+            Extent3d {
+                width: MATERIAL_TABLE_WIDTH,
+                height,
+                depth_or_array_layers: 1,
+            },
+            TextureDimension::D2,
+            data,
+            TextureFormat::Rgba8Unorm,
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::voxel::{DetailPattern, Rgba};
+
+    use super::*;
+
+    #[test]
+    fn test_material_table() {
+        let mut table = MaterialTable::default();
+        let red = PbrProps {
+            color: Rgba::from(Color::RED),
+            emission: 7,
+            ..default()
+        };
+        let blue = PbrProps {
+            color: Rgba::from(Color::BLUE),
+            metallic: 9,
+            ..default()
+        };
+
+        assert_eq!(table.index_of(red), 0);
+        assert_eq!(table.index_of(blue), 1);
+        assert_eq!(table.index_of(red), 0);
+
+        let bevelled = PbrProps {
+            pattern: DetailPattern::Bevel,
+            ..red
+        };
+        assert_eq!(table.index_of(bevelled), 0);
+
+        let image = table.to_image();
+        assert_eq!(image.size(), Vec2::new(MATERIAL_TABLE_WIDTH as f32, 1.0));
+        assert_eq!(image.data[0..8], [255, 0, 0, 255, 0, 0, 0, 7]);
+        assert_eq!(image.data[8..16], [0, 0, 255, 255, 9, 0, 0, 0]);
+    }
+
+    #[test]
+    fn test_material_table_reuse() {
+        let mut table = MaterialTable::default();
+        let shade = |r: u8| PbrProps {
+            color: Rgba {
+                r,
+                g: 0,
+                b: 0,
+                a: 255,
+            },
+            ..default()
+        };
+        let a = (ChunkCoord(IVec3::ZERO), MeshLayer::Opaque);
+        let b = (ChunkCoord(IVec3::X), MeshLayer::Opaque);
+
+        // Two meshes share a material, one also uses another.
+        let (red, dark) = (table.index_of(shade(255)), table.index_of(shade(100)));
+        table.set_mesh_materials(a, vec![red, dark]);
+        table.set_mesh_materials(b, vec![red]);
+
+        // Preview frames remesh `a` with ever new materials, which take over the freed slot.
+        for r in 0..50 {
+            let index = table.index_of(shade(r));
+            table.set_mesh_materials(a, vec![red, index]);
+        }
+        assert_eq!(table.props.len(), 3);
+
+        // Removing `a` keeps the material `b` still uses.
+        table.set_mesh_materials(a, vec![]);
+        assert_eq!(table.index_of(shade(255)), red);
+        assert_eq!(table.free.len(), 2);
+    }
+}
@@ -1,26 +1,58 @@
 use bevy::{
-    pbr::{MaterialPipeline, MaterialPipelineKey},
+    pbr::{MaterialPipeline, MaterialPipelineKey, MeshPipelineKey},
     prelude::*,
     reflect::TypeUuid,
     render::{
         mesh::{Indices, MeshVertexAttribute, MeshVertexBufferLayout, VertexAttributeValues},
+        primitives::Aabb,
         render_resource::{
             AsBindGroup, PrimitiveTopology, RenderPipelineDescriptor, ShaderRef,
             SpecializedMeshPipelineError, VertexFormat,
         },
+        texture::{CompressedImageFormats, ImageType},
     },
 };
 
-use super::{Buffer, FastBufferReader, WorldCoord};
+use super::{
+    Buffer, ChunkCoord, FastBufferReader, LocalCoord, MaterialTable, PaddedOccupancy, PbrProps,
+    WorldCoord, PADDED_INNER, WIDTH,
+};
+
+/// A whole blocky vertex, packed into two u32s. The first holds the chunk-local integer position
+/// (7 bits per axis from bit 0, offset by `POSITION_BIAS`), the face index into `NORM_TAN_BITAN`
+/// (bits 21-23), the AO level (bits 24-25), the corner of the face (bits 26-27, which make the
+/// face's UVs) and the `DetailPattern` (bits 28-29). The second is the index of the voxel's
+/// PbrProps in the entity's `MaterialTable`. Decoded by `voxel_vert.wgsl`.
+///
+/// That's 8 bytes per vertex. Without a `Mesh::ATTRIBUTE_POSITION`, Bevy can't compute the bounds
+/// of blocky meshes (see `packed_mesh_aabb`) or draw them into its shadow maps (see
+/// `VoxelShadowPlugin`).
+pub const ATTRIBUTE_PACKED: MeshVertexAttribute =
+    MeshVertexAttribute::new("Vertex_Packed", 413207316, VertexFormat::Uint32x2);
 
-const ATTRIBUTE_COLOR_EMISSIVE: MeshVertexAttribute =
+/// Added to packed positions so they're never negative. Translucent chunks are centered on their
+/// origin, so their positions go from -WIDTH / 2 to WIDTH / 2.
+const POSITION_BIAS: i32 = 32;
+
+/// Color and emission of smooth vertices, which blend PbrProps and so can't use a `MaterialTable`.
+pub const ATTRIBUTE_COLOR_EMISSIVE: MeshVertexAttribute =
     MeshVertexAttribute::new("Vertex_Color_Emissive", 956190401, VertexFormat::Unorm8x4);
 
+/// Metallic, roughness and reflectance of smooth vertices. The last component is unused.
 pub const ATTRIBUTE_PBR_NORM: MeshVertexAttribute =
     MeshVertexAttribute::new("Vertex_Pbr_Norm", 569923874, VertexFormat::Uint8x4);
 
+/// The ambient occlusion level of smooth vertices, from 0 (fully occluded) to 3 (unoccluded).
+/// Mapped to a brightness by `VoxelMaterial::ao_curve`, like the AO level in `ATTRIBUTE_PACKED`.
+pub const ATTRIBUTE_AO: MeshVertexAttribute =
+    MeshVertexAttribute::new("Vertex_Ao", 210584663, VertexFormat::Uint32);
+
+/// Opacity of smooth vertices.
+pub const ATTRIBUTE_OPACITY: MeshVertexAttribute =
+    MeshVertexAttribute::new("Vertex_Opacity", 730164329, VertexFormat::Float32);
+
 /// Tuples of Quad Origin, Normal, Tangent, Bitangent
-const NORM_TAN_BITAN: [(IVec3, IVec3, IVec3, IVec3); 6] = [
+pub(super) const NORM_TAN_BITAN: [(IVec3, IVec3, IVec3, IVec3); 6] = [
     (IVec3::ZERO, IVec3::NEG_X, IVec3::Z, IVec3::Y),
     (IVec3::Z, IVec3::Z, IVec3::X, IVec3::Y),
     (IVec3::new(1, 0, 1), IVec3::X, IVec3::NEG_Z, IVec3::Y),
@@ -29,87 +61,232 @@ const NORM_TAN_BITAN: [(IVec3, IVec3, IVec3, IVec3); 6] = [
     (IVec3::ZERO, IVec3::NEG_Y, IVec3::X, IVec3::Z),
 ];
 
-impl From<&Buffer> for Mesh {
-    fn from(buffer: &Buffer) -> Self {
-        let mut positions: Vec<IVec3> = Vec::new();
-        let mut pbr_norm: Vec<[u8; 4]> = Vec::new();
-        let mut color_emissive: Vec<[u8; 4]> = Vec::new();
-        let mut indexes: Vec<u32> = Vec::new();
-        let mut reader = FastBufferReader::new(buffer);
+/// Opaque and translucent voxels are meshed separately, as translucent ones need to be drawn
+/// blended, after everything opaque.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum MeshLayer {
+    Opaque,
+    Translucent,
+}
+
+impl MeshLayer {
+    pub fn contains(&self, props: &PbrProps) -> bool {
+        match self {
+            MeshLayer::Opaque => props.is_opaque(),
+            MeshLayer::Translucent => props.is_translucent(),
+        }
+    }
+
+    /// The origin chunk meshes of this layer are relative to. Translucent chunks are centered, as
+    /// Bevy sorts blended meshes back-to-front by their translation.
+    pub fn chunk_origin(&self, chunk_coord: ChunkCoord) -> IVec3 {
+        match self {
+            MeshLayer::Opaque => chunk_coord.first_cell_coord().0,
+            MeshLayer::Translucent => {
+                chunk_coord.first_cell_coord().0 + IVec3::splat(WIDTH as i32 / 2)
+            }
+        }
+    }
+}
 
-        for chunk_coord in buffer.chunks.keys() {
-            for WorldCoord(coord) in chunk_coord.iter_world_coords() {
-                let props = reader.get(WorldCoord(coord));
+/// Meshes a single layer of a single chunk of the buffer, with vertex positions relative to
+/// `MeshLayer::chunk_origin`. Neighboring chunks are still read for face culling and AO, so the
+/// result is seamless with the meshes of adjacent chunks. PbrProps are added to `table`, which the
+/// vertices index into. Returns None if there is nothing to draw.
+pub fn mesh_chunk(
+    buffer: &Buffer,
+    chunk_coord: ChunkCoord,
+    layer: MeshLayer,
+    table: &mut MaterialTable,
+) -> Option<Mesh> {
+    let mut builder = MeshBuilder::default();
+    let mut reader = FastBufferReader::new(buffer);
+    builder.add_chunk(
+        &mut reader,
+        table,
+        chunk_coord,
+        layer,
+        layer.chunk_origin(chunk_coord),
+    );
 
-                if props == default() {
+    if builder.packed.is_empty() {
+        None
+    } else {
+        Some(builder.build())
+    }
+}
+
+/// Whether the face of a voxel against the given neighbor voxel is visible. Opaque faces are only
+/// hidden by opaque neighbors. Translucent faces are hidden by anything other than empty space,
+/// except a different translucent material (so glass against water still has a surface).
+fn face_visible(props: &PbrProps, neighbor: &PbrProps) -> bool {
+    if props.is_opaque() {
+        !neighbor.is_opaque()
+    } else {
+        neighbor.is_empty() || (neighbor.is_translucent() && neighbor != props)
+    }
+}
+
+#[derive(Default)]
+struct MeshBuilder {
+    packed: Vec<[u32; 2]>,
+    indexes: Vec<u32>,
+}
+
+impl MeshBuilder {
+    /// Adds the faces of all voxels of the layer in the chunk, with positions offset by `-origin`.
+    /// Faces are culled a row of voxels at a time against the occupancy masks of the chunk and its
+    /// neighbors, so PbrProps are only read for voxels that have a visible face.
+    fn add_chunk(
+        &mut self,
+        reader: &mut FastBufferReader,
+        table: &mut MaterialTable,
+        chunk_coord: ChunkCoord,
+        layer: MeshLayer,
+        origin: IVec3,
+    ) {
+        let chunk = match reader.buffer().chunks.get(&chunk_coord) {
+            Some(chunk) => chunk,
+            None => return,
+        };
+        let occupied = PaddedOccupancy::new(reader.buffer(), chunk_coord, |c| &c.occupied);
+        let opaque = PaddedOccupancy::new(reader.buffer(), chunk_coord, |c| &c.opaque);
+        let first = chunk_coord.first_cell_coord().0;
+
+        for z in 0..WIDTH as i32 {
+            for y in 0..WIDTH as i32 {
+                let voxels = PADDED_INNER
+                    & match layer {
+                        MeshLayer::Opaque => opaque.row(y, z),
+                        MeshLayer::Translucent => occupied.row(y, z) & !opaque.row(y, z),
+                    };
+                if voxels == 0 {
                     continue;
                 }
 
-                for (i, (origin, norm, tan, bi_tan)) in NORM_TAN_BITAN.into_iter().enumerate() {
-                    if reader.get(WorldCoord(coord + norm)) != default() {
-                        continue;
+                // For each face, the voxels whose neighbor on that side certainly doesn't hide it,
+                // and (translucent only) those whose neighbor is translucent, which hides it only
+                // if it's the same material.
+                let mut visible = [0; 6];
+                let mut compare = [0; 6];
+                for (i, (_, norm, _, _)) in NORM_TAN_BITAN.into_iter().enumerate() {
+                    let neighbor_opaque = opaque.neighbor_row(y, z, norm);
+                    match layer {
+                        MeshLayer::Opaque => visible[i] = voxels & !neighbor_opaque,
+                        MeshLayer::Translucent => {
+                            let neighbor_occupied = occupied.neighbor_row(y, z, norm);
+                            visible[i] = voxels & !neighbor_occupied;
+                            compare[i] = voxels & neighbor_occupied & !neighbor_opaque;
+                        }
                     }
+                }
 
-                    // The 8 surrounding voxels for fake ambient occlusion.
-                    let ao_c = coord + norm;
-
-                    let ao_r = reader.get(WorldCoord(ao_c + tan)).color.a != 0;
-                    let ao_l = reader.get(WorldCoord(ao_c - tan)).color.a != 0;
-                    let ao_u = reader.get(WorldCoord(ao_c + bi_tan)).color.a != 0;
-                    let ao_d = reader.get(WorldCoord(ao_c - bi_tan)).color.a != 0;
-                    let ao_ur = reader.get(WorldCoord(ao_c + tan + bi_tan)).color.a != 0;
-                    let ao_lr = reader.get(WorldCoord(ao_c + tan - bi_tan)).color.a != 0;
-                    let ao_ul = reader.get(WorldCoord(ao_c - tan + bi_tan)).color.a != 0;
-                    let ao_ll = reader.get(WorldCoord(ao_c - tan - bi_tan)).color.a != 0;
-
-                    // Now shadow the 4 corner colors
-                    let p = coord + origin;
-                    let c_ll = props.color.shadow(ao_ll || ao_d || ao_l);
-                    let c_lr = props.color.shadow(ao_lr || ao_d || ao_r);
-                    let c_ur = props.color.shadow(ao_ur || ao_r || ao_u);
-                    let c_ul = props.color.shadow(ao_ul || ao_l || ao_u);
-
-                    positions.extend([p, p + tan, p + tan + bi_tan, p + bi_tan]);
-                    pbr_norm
-                        .extend([[props.metallic, props.roughness, props.reflectance, i as u8]; 4]);
-                    color_emissive.extend([
-                        [c_ll.r, c_ll.g, c_ll.b, props.emission],
-                        [c_lr.r, c_lr.g, c_lr.b, props.emission],
-                        [c_ur.r, c_ur.g, c_ur.b, props.emission],
-                        [c_ul.r, c_ul.g, c_ul.b, props.emission],
-                    ]);
-                    indexes.extend(
-                        [0, 1, 2, 0, 2, 3]
-                            .iter()
-                            .map(|i| i + (positions.len() - 4) as u32),
-                    );
+                let mut bits = visible.iter().chain(compare.iter()).fold(0, |a, b| a | b);
+                while bits != 0 {
+                    let x = bits.trailing_zeros() as i32 - 1;
+                    bits &= bits - 1;
+
+                    let local = IVec3::new(x, y, z);
+                    let coord = first + local;
+                    let props = chunk.get(LocalCoord(local.as_uvec3()));
+                    let bit = 1 << (x + 1);
+                    let material_index = table.index_of(props);
+
+                    for (i, (_, norm, _, _)) in NORM_TAN_BITAN.into_iter().enumerate() {
+                        let shown = visible[i] & bit != 0
+                            || (compare[i] & bit != 0
+                                && face_visible(&props, &reader.get(WorldCoord(coord + norm))));
+                        if shown {
+                            self.add_face(&opaque, i, local, coord - origin, props, material_index);
+                        }
+                    }
                 }
             }
         }
+    }
 
-        let mut mesh = Mesh::new(PrimitiveTopology::TriangleList);
+    /// Adds face `i` (into `NORM_TAN_BITAN`) of the voxel at chunk-local `local`, at position `p`.
+    fn add_face(
+        &mut self,
+        opaque: &PaddedOccupancy,
+        i: usize,
+        local: IVec3,
+        p: IVec3,
+        props: PbrProps,
+        material_index: u32,
+    ) {
+        let (quad_origin, norm, tan, bi_tan) = NORM_TAN_BITAN[i];
 
-        let use_u32_indexes = positions.len() > u16::MAX as usize;
+        // The 8 surrounding voxels for ambient occlusion. They're all perpendicular to the normal,
+        // so always within the 1 voxel border of the padded mask.
+        let ao_c = local + norm;
 
-        mesh.insert_attribute(
-            Mesh::ATTRIBUTE_POSITION,
+        let ao_r = opaque.get(ao_c + tan);
+        let ao_l = opaque.get(ao_c - tan);
+        let ao_u = opaque.get(ao_c + bi_tan);
+        let ao_d = opaque.get(ao_c - bi_tan);
+        let ao_ur = opaque.get(ao_c + tan + bi_tan);
+        let ao_lr = opaque.get(ao_c + tan - bi_tan);
+        let ao_ul = opaque.get(ao_c - tan + bi_tan);
+        let ao_ll = opaque.get(ao_c - tan - bi_tan);
+
+        // AO levels of the 4 corners, in the same order as the positions below.
+        let ao = [
+            vertex_ao(ao_l, ao_d, ao_ll),
+            vertex_ao(ao_r, ao_d, ao_lr),
+            vertex_ao(ao_r, ao_u, ao_ur),
+            vertex_ao(ao_l, ao_u, ao_ul),
+        ];
+
+        let p = p + quad_origin;
+        let positions = [p, p + tan, p + tan + bi_tan, p + bi_tan];
+
+        self.packed.extend(
             positions
-                .into_iter()
-                .map(|p| p.as_vec3().to_array())
-                .collect::<Vec<_>>(),
+                .iter()
+                .zip(ao)
+                .enumerate()
+                .map(|(corner, (p, ao))| {
+                    pack_vertex(
+                        *p,
+                        i as u32,
+                        ao as u32,
+                        corner as u32,
+                        props.pattern as u32,
+                        material_index,
+                    )
+                }),
         );
 
-        mesh.insert_attribute(ATTRIBUTE_PBR_NORM, VertexAttributeValues::Uint8x4(pbr_norm));
+        // Split the quad along the diagonal with the brighter pair of corners, otherwise the
+        // interpolated AO is anisotropic (a dark corner smears across the whole quad).
+        let base = (self.packed.len() - 4) as u32;
+        let tris = if ao[0] + ao[2] >= ao[1] + ao[3] {
+            [0, 1, 2, 0, 2, 3]
+        } else {
+            [1, 2, 3, 1, 3, 0]
+        };
+        self.indexes.extend(tris.iter().map(|i| i + base));
+    }
+
+    fn build(self) -> Mesh {
+        let mut mesh = Mesh::new(PrimitiveTopology::TriangleList);
+
+        let use_u32_indexes = self.packed.len() > u16::MAX as usize;
+
         mesh.insert_attribute(
-            ATTRIBUTE_COLOR_EMISSIVE,
-            VertexAttributeValues::Unorm8x4(color_emissive),
+            ATTRIBUTE_PACKED,
+            VertexAttributeValues::Uint32x2(self.packed),
         );
 
         if use_u32_indexes {
-            mesh.set_indices(Some(Indices::U32(indexes)));
+            mesh.set_indices(Some(Indices::U32(self.indexes)));
         } else {
             mesh.set_indices(Some(Indices::U16(
-                indexes.into_iter().map(|i| i as u16).collect::<Vec<u16>>(),
+                self.indexes
+                    .into_iter()
+                    .map(|i| i as u16)
+                    .collect::<Vec<u16>>(),
             )));
         }
 
@@ -117,9 +294,162 @@ impl From<&Buffer> for Mesh {
     }
 }
 
+/// Packs a vertex of `ATTRIBUTE_PACKED`.
+fn pack_vertex(
+    position: IVec3,
+    face: u32,
+    ao: u32,
+    corner: u32,
+    pattern: u32,
+    material_index: u32,
+) -> [u32; 2] {
+    let p = (position + POSITION_BIAS).as_uvec3();
+    [
+        p.x | p.y << 7 | p.z << 14 | face << 21 | ao << 24 | corner << 26 | pattern << 28,
+        material_index,
+    ]
+}
+
+/// The position of a vertex of `ATTRIBUTE_PACKED`.
+fn unpack_position(packed: [u32; 2]) -> IVec3 {
+    let p = UVec3::new(packed[0], packed[0] >> 7, packed[0] >> 14) & UVec3::splat(0x7f);
+    p.as_ivec3() - POSITION_BIAS
+}
+
+/// The distinct `MaterialTable` slots used by a mesh with `ATTRIBUTE_PACKED` vertices, empty for
+/// any other mesh.
+pub fn packed_mesh_materials(mesh: &Mesh) -> Vec<u32> {
+    let mut materials = match mesh.attribute(ATTRIBUTE_PACKED) {
+        Some(VertexAttributeValues::Uint32x2(packed)) => {
+            packed.iter().map(|p| p[1]).collect::<Vec<_>>()
+        }
+        _ => return vec![],
+    };
+
+    materials.sort_unstable();
+    materials.dedup();
+    materials
+}
+
+/// The bounds of a mesh with `ATTRIBUTE_PACKED` vertices, which `Mesh::compute_aabb` can't see.
+pub fn packed_mesh_aabb(mesh: &Mesh) -> Option<Aabb> {
+    let packed = match mesh.attribute(ATTRIBUTE_PACKED) {
+        Some(VertexAttributeValues::Uint32x2(packed)) => packed,
+        _ => return None,
+    };
+
+    let positions = packed.iter().map(|p| unpack_position(*p));
+    let min = positions.clone().reduce(IVec3::min)?;
+    let max = positions.reduce(IVec3::max)?;
+    Some(Aabb::from_min_max(min.as_vec3(), max.as_vec3()))
+}
+
+/// The classic voxel AO level of a vertex from its two side neighbors and the corner neighbor
+/// between them. 0 is fully occluded, 3 is unoccluded. Both sides being solid occludes the corner
+/// entirely, regardless of the corner voxel itself.
+fn vertex_ao(side1: bool, side2: bool, corner: bool) -> u8 {
+    if side1 && side2 {
+        0
+    } else {
+        3 - (side1 as u8 + side2 as u8 + corner as u8)
+    }
+}
+
+/// The bundled normal maps of each `DetailPattern`, shared by all voxel materials.
+#[derive(Resource)]
+pub struct VoxelDetailTextures {
+    pub panel: Handle<Image>,
+    pub bevel: Handle<Image>,
+}
+
+impl FromWorld for VoxelDetailTextures {
+    fn from_world(world: &mut World) -> Self {
+        let mut images = world.resource_mut::<Assets<Image>>();
+
+        // Decoded here rather than by the asset server, which would treat them as sRGB colors.
+        let mut add = |bytes: &[u8]| {
+            let image = Image::from_buffer(
+                bytes,
+                ImageType::Extension("png"),
+                CompressedImageFormats::NONE,
+                false,
+            )
+            .expect("bundled normal maps are valid PNGs");
+            images.add(image)
+        };
+
+        Self {
+            panel: add(include_bytes!("../../assets/textures/normal.png")),
+            bevel: add(include_bytes!("../../assets/textures/normal_round.png")),
+        }
+    }
+}
+
 #[derive(AsBindGroup, Debug, Clone, TypeUuid)]
 #[uuid = "8cc0d9ab-e0ed-4a7d-b677-bbb8f0a00c41"]
-pub struct VoxelMaterial {}
+#[bind_group_data(VoxelMaterialKey)]
+pub struct VoxelMaterial {
+    /// Brightness multiplier for each AO level, from fully occluded (x) to unoccluded (w).
+    #[uniform(0)]
+    pub ao_curve: Vec4,
+
+    /// Normal map of `DetailPattern::Panel`. Panel voxels are drawn flat without one.
+    #[texture(1)]
+    #[sampler(2)]
+    pub panel_normal_map: Option<Handle<Image>>,
+
+    /// The `MaterialTable::image` that blocky vertices index into.
+    #[texture(3)]
+    pub material_table: Handle<Image>,
+
+    /// Normal map of `DetailPattern::Bevel`. Bevel voxels are drawn flat without one.
+    #[texture(4)]
+    #[sampler(5)]
+    pub bevel_normal_map: Option<Handle<Image>>,
+
+    /// `AlphaMode::Blend` for the material of translucent chunk meshes, otherwise opaque.
+    pub alpha_mode: AlphaMode,
+}
+
+#[derive(Clone, PartialEq, Eq, Hash)]
+pub struct VoxelMaterialKey {
+    panel_normal_map: bool,
+    bevel_normal_map: bool,
+}
+
+impl From<&VoxelMaterial> for VoxelMaterialKey {
+    fn from(material: &VoxelMaterial) -> Self {
+        Self {
+            panel_normal_map: material.panel_normal_map.is_some(),
+            bevel_normal_map: material.bevel_normal_map.is_some(),
+        }
+    }
+}
+
+impl VoxelMaterial {
+    pub fn for_layer(layer: MeshLayer, material_table: Handle<Image>) -> Self {
+        Self {
+            material_table,
+            alpha_mode: match layer {
+                MeshLayer::Opaque => AlphaMode::Opaque,
+                MeshLayer::Translucent => AlphaMode::Blend,
+            },
+            ..default()
+        }
+    }
+}
+
+impl Default for VoxelMaterial {
+    fn default() -> Self {
+        Self {
+            ao_curve: Vec4::new(0.4, 0.6, 0.8, 1.0),
+            panel_normal_map: None,
+            material_table: default(),
+            bevel_normal_map: None,
+            alpha_mode: AlphaMode::Opaque,
+        }
+    }
+}
 
 impl Material for VoxelMaterial {
     fn vertex_shader() -> ShaderRef {
@@ -130,18 +460,132 @@ impl Material for VoxelMaterial {
         "shaders/voxel_frag.wgsl".into()
     }
 
+    fn alpha_mode(&self) -> AlphaMode {
+        self.alpha_mode
+    }
+
     fn specialize(
         _pipeline: &MaterialPipeline<Self>,
         descriptor: &mut RenderPipelineDescriptor,
         layout: &MeshVertexBufferLayout,
-        _key: MaterialPipelineKey<Self>,
+        key: MaterialPipelineKey<Self>,
     ) -> Result<(), SpecializedMeshPipelineError> {
-        let vertex_layout = layout.get_layout(&[
-            Mesh::ATTRIBUTE_POSITION.at_shader_location(0),
-            ATTRIBUTE_PBR_NORM.at_shader_location(1),
-            ATTRIBUTE_COLOR_EMISSIVE.at_shader_location(2),
-        ])?;
-        descriptor.vertex.buffers = vec![vertex_layout];
+        // Smooth meshes carry real positions, normals and blended PbrProps instead of
+        // `ATTRIBUTE_PACKED`.
+        let attributes = if layout.contains(ATTRIBUTE_PACKED) {
+            vec![ATTRIBUTE_PACKED.at_shader_location(0)]
+        } else {
+            descriptor.vertex.shader_defs.push("VOXEL_SMOOTH".into());
+            vec![
+                Mesh::ATTRIBUTE_POSITION.at_shader_location(0),
+                ATTRIBUTE_PBR_NORM.at_shader_location(1),
+                ATTRIBUTE_COLOR_EMISSIVE.at_shader_location(2),
+                ATTRIBUTE_AO.at_shader_location(3),
+                ATTRIBUTE_OPACITY.at_shader_location(4),
+                Mesh::ATTRIBUTE_NORMAL.at_shader_location(5),
+            ]
+        };
+
+        descriptor.vertex.buffers = vec![layout.get_layout(&attributes)?];
+
+        if let Some(fragment) = descriptor.fragment.as_mut() {
+            if key
+                .mesh_key
+                .contains(MeshPipelineKey::TRANSPARENT_MAIN_PASS)
+            {
+                fragment.shader_defs.push("VOXEL_TRANSLUCENT".into());
+            }
+            if key.bind_group_data.panel_normal_map {
+                fragment.shader_defs.push("VOXEL_PANEL_NORMAL_MAP".into());
+            }
+            if key.bind_group_data.bevel_normal_map {
+                fragment.shader_defs.push("VOXEL_BEVEL_NORMAL_MAP".into());
+            }
+        }
+
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::voxel::{DetailPattern, Rgba};
+
+    use super::*;
+
+    #[test]
+    fn test_vertex_ao() {
+        assert_eq!(vertex_ao(false, false, false), 3);
+        assert_eq!(vertex_ao(false, false, true), 2);
+        assert_eq!(vertex_ao(true, false, false), 2);
+        assert_eq!(vertex_ao(true, false, true), 1);
+        assert_eq!(vertex_ao(true, true, false), 0);
+        assert_eq!(vertex_ao(true, true, true), 0);
+    }
+
+    #[test]
+    fn test_mesh_chunk() {
+        let mut buffer = Buffer::default();
+        let mut table = MaterialTable::default();
+        let p = PbrProps {
+            color: Rgba::from(Color::RED),
+            pattern: DetailPattern::Bevel,
+            ..default()
+        };
+        buffer.set(WorldCoord(IVec3::new(1, 0, 0)), p);
+
+        let mesh = mesh_chunk(
+            &buffer,
+            ChunkCoord(IVec3::ZERO),
+            MeshLayer::Opaque,
+            &mut table,
+        );
+        let mesh = mesh.unwrap();
+        let packed = match mesh.attribute(ATTRIBUTE_PACKED) {
+            Some(VertexAttributeValues::Uint32x2(packed)) => packed.clone(),
+            _ => panic!("missing packed attribute"),
+        };
+
+        assert_eq!(packed.len(), 6 * 4);
+        assert_eq!(packed[0], pack_vertex(IVec3::X, 0, 3, 0, 2, 0));
+        assert_eq!(
+            packed[packed.len() - 1],
+            pack_vertex(IVec3::new(1, 0, 1), 5, 3, 3, 2, 0)
+        );
+        assert_eq!(table.index_of(p), 0);
+
+        let aabb = packed_mesh_aabb(&mesh).unwrap();
+        assert_eq!(Vec3::from(aabb.min()), Vec3::new(1.0, 0.0, 0.0));
+        assert_eq!(Vec3::from(aabb.max()), Vec3::new(2.0, 1.0, 1.0));
+
+        // Centered translucent positions survive the round trip.
+        let corner = IVec3::new(-16, 16, -3);
+        assert_eq!(unpack_position(pack_vertex(corner, 0, 0, 0, 0, 0)), corner);
+    }
+
+    #[test]
+    fn test_face_visible() {
+        let empty = PbrProps::default();
+        let solid = PbrProps {
+            color: Rgba::from(Color::RED),
+            ..default()
+        };
+        let glass = PbrProps {
+            color: Rgba::from(Color::rgba(1.0, 1.0, 1.0, 0.5)),
+            ..default()
+        };
+        let water = PbrProps {
+            color: Rgba::from(Color::rgba(0.0, 0.0, 1.0, 0.5)),
+            ..default()
+        };
+
+        assert!(face_visible(&solid, &empty));
+        assert!(face_visible(&solid, &glass));
+        assert!(!face_visible(&solid, &solid));
+
+        assert!(face_visible(&glass, &empty));
+        assert!(face_visible(&glass, &water));
+        assert!(!face_visible(&glass, &glass));
+        assert!(!face_visible(&glass, &solid));
+    }
+}
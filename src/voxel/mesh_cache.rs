@@ -0,0 +1,209 @@
+use std::{
+    collections::{hash_map::DefaultHasher, HashMap},
+    hash::{Hash, Hasher},
+};
+
+use bevy::{prelude::*, render::primitives::Aabb};
+use bevy_rapier3d::prelude::*;
+
+use super::{
+    Buffer, ChunkCoord, MaterialTable, MeshLayer, Mesher, VoxelChunks, VoxelColliderMode,
+    VoxelColliders,
+};
+
+/// A hash of the contents of a `Buffer`. Buffers with the same voxels hash the same, however they
+/// were built.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct VolumeHash(pub u64);
+
+impl VolumeHash {
+    pub fn of(buffer: &Buffer) -> Self {
+        // Chunks are hashed separately and summed, as the order of the chunk map isn't stable.
+        let hash = buffer
+            .chunks
+            .iter()
+            .map(|(chunk_coord, chunk)| {
+                let mut hasher = DefaultHasher::new();
+                chunk_coord.0.hash(&mut hasher);
+                chunk.content_hash().hash(&mut hasher);
+                hasher.finish()
+            })
+            .fold(0u64, u64::wrapping_add);
+
+        Self(hash)
+    }
+}
+
+/// The chunk meshes of a volume, as built by `VoxelChunks`.
+pub struct CachedVolume {
+    pub meshes: HashMap<(ChunkCoord, MeshLayer), (Handle<Mesh>, Aabb)>,
+
+    /// The table the blocky meshes index into.
+    pub material_table: MaterialTable,
+}
+
+/// The chunk colliders of a volume, as built by `VoxelColliders`.
+pub struct CachedColliders {
+    pub colliders: HashMap<ChunkCoord, Collider>,
+}
+
+/// Meshes and colliders of voxel volumes by content, so entities with the same volume (the same
+/// prop placed 50 times) share one set of GPU meshes and collider shapes instead of building their
+/// own. Entries are reference counted by the entities using them, and dropped (along with their
+/// handles) once none are.
+#[derive(Resource, Default)]
+pub struct VoxelMeshCache {
+    volumes: RefCounted<(VolumeHash, Mesher), CachedVolume>,
+    colliders: RefCounted<(VolumeHash, VoxelColliderMode), CachedColliders>,
+}
+
+impl VoxelMeshCache {
+    pub fn volume(&self, key: (VolumeHash, Mesher)) -> Option<&CachedVolume> {
+        self.volumes.get(&key)
+    }
+
+    pub fn colliders(&self, key: (VolumeHash, VoxelColliderMode)) -> Option<&CachedColliders> {
+        self.colliders.get(&key)
+    }
+
+    /// Marks the entity as using the volume's meshes, caching the current meshes of `chunks` for
+    /// it if it isn't yet. Releases whatever volume the entity used before.
+    pub fn acquire_volume(
+        &mut self,
+        entity: Entity,
+        key: (VolumeHash, Mesher),
+        chunks: &VoxelChunks,
+    ) {
+        self.volumes.acquire(entity, key, || CachedVolume {
+            meshes: chunks.meshes.clone(),
+            material_table: chunks.material_table.clone(),
+        });
+    }
+
+    /// Marks the entity as using the volume's colliders, caching the current colliders of
+    /// `colliders` for it if it isn't yet. Releases whatever colliders the entity used before.
+    pub fn acquire_colliders(
+        &mut self,
+        entity: Entity,
+        key: (VolumeHash, VoxelColliderMode),
+        colliders: &VoxelColliders,
+    ) {
+        self.colliders.acquire(entity, key, || CachedColliders {
+            colliders: colliders.colliders.clone(),
+        });
+    }
+
+    /// The number of distinct volumes with cached meshes.
+    pub fn volume_count(&self) -> usize {
+        self.volumes.entries.len()
+    }
+}
+
+/// Releases the cached volumes of entities that no longer render or collide with voxels.
+pub fn release_cached_volumes(
+    mut cache: ResMut<VoxelMeshCache>,
+    removed_chunks: RemovedComponents<VoxelChunks>,
+    removed_colliders: RemovedComponents<VoxelColliders>,
+) {
+    for entity in removed_chunks.iter() {
+        cache.volumes.release(entity);
+    }
+    for entity in removed_colliders.iter() {
+        cache.colliders.release(entity);
+    }
+}
+
+/// Values shared by entities, each using at most one, dropped once none use them.
+struct RefCounted<K, V> {
+    /// Values and the number of entities using them.
+    entries: HashMap<K, (V, usize)>,
+    users: HashMap<Entity, K>,
+}
+
+impl<K, V> Default for RefCounted<K, V> {
+    fn default() -> Self {
+        Self {
+            entries: default(),
+            users: default(),
+        }
+    }
+}
+
+impl<K, V> RefCounted<K, V>
+where
+    K: Copy + Eq + Hash,
+{
+    fn get(&self, key: &K) -> Option<&V> {
+        self.entries.get(key).map(|(value, _)| value)
+    }
+
+    fn acquire<F>(&mut self, entity: Entity, key: K, value: F)
+    where
+        F: FnOnce() -> V,
+    {
+        if self.users.get(&entity) == Some(&key) {
+            return;
+        }
+
+        self.release(entity);
+        self.entries.entry(key).or_insert_with(|| (value(), 0)).1 += 1;
+        self.users.insert(entity, key);
+    }
+
+    fn release(&mut self, entity: Entity) {
+        let key = unwrap_or_return!(self.users.remove(&entity));
+        if let Some((_, count)) = self.entries.get_mut(&key) {
+            *count -= 1;
+            if *count == 0 {
+                self.entries.remove(&key);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::voxel::{PbrProps, Rgba, WorldCoord};
+
+    use super::*;
+
+    #[test]
+    fn test_volume_hash() {
+        let p = PbrProps {
+            color: Rgba::from(Color::RED),
+            ..default()
+        };
+
+        let mut a = Buffer::default();
+        a.set(WorldCoord(IVec3::new(1, 2, 3)), p);
+        a.set(WorldCoord(IVec3::new(-50, 2, 3)), p);
+
+        // The same voxels set in a different order, with a detour.
+        let mut b = Buffer::default();
+        b.set(WorldCoord(IVec3::new(-50, 2, 3)), p);
+        b.set(WorldCoord(IVec3::new(9, 9, 9)), p);
+        b.set(WorldCoord(IVec3::new(1, 2, 3)), p);
+        assert_ne!(VolumeHash::of(&a), VolumeHash::of(&b));
+
+        b.set(WorldCoord(IVec3::new(9, 9, 9)), default());
+        assert_eq!(VolumeHash::of(&a), VolumeHash::of(&b));
+    }
+
+    #[test]
+    fn test_ref_counted() {
+        let mut cache: RefCounted<u32, &str> = default();
+        let (a, b) = (Entity::from_raw(0), Entity::from_raw(1));
+
+        cache.acquire(a, 1, || "one");
+        cache.acquire(b, 1, || "not built, already cached");
+        assert_eq!(cache.get(&1), Some(&"one"));
+
+        // Moving one entity to another value keeps the first alive for the other.
+        cache.acquire(a, 2, || "two");
+        assert_eq!(cache.get(&1), Some(&"one"));
+
+        cache.release(b);
+        assert_eq!(cache.get(&1), None);
+        assert_eq!(cache.get(&2), Some(&"two"));
+    }
+}
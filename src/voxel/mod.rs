@@ -1,12 +1,38 @@
 mod buffer;
+mod chunk_mesh;
+mod collider;
 mod compressed_chunk;
 mod coords;
+mod emissive;
+mod flood;
+mod material_table;
 mod mesh;
+mod mesh_cache;
+mod occupancy;
+mod octree;
+mod picking;
 mod props;
 mod raycast;
+mod shadow;
+mod storage;
+mod surface_nets;
+mod sweep;
 
 pub use buffer::*;
+pub use chunk_mesh::*;
+pub use collider::*;
 pub use coords::*;
+pub use emissive::*;
+pub use flood::*;
+pub use material_table::*;
 pub use mesh::*;
+pub use mesh_cache::*;
+pub use occupancy::*;
+pub use octree::*;
+pub use picking::*;
 pub use props::*;
 pub use raycast::*;
+pub use shadow::*;
+pub use storage::*;
+pub use surface_nets::*;
+pub use sweep::*;
@@ -0,0 +1,223 @@
+use bevy::prelude::*;
+
+use super::{Buffer, Chunk, ChunkCoord, LocalCoord, LN_SIZE, WIDTH};
+
+/// Log2 of the width of a brick, the coarse level of an `OccupancyMask`.
+pub const BRICK_LN: u32 = 3;
+
+/// Width of a brick, in voxels. A chunk is 4x4x4 bricks, so one `u64` covers all of them.
+pub const BRICK_WIDTH: u32 = 1 << BRICK_LN;
+
+const BRICKS_PER_AXIS: u32 = WIDTH as u32 / BRICK_WIDTH;
+
+/// One bit per voxel of a chunk, for answering "is anything there" without comparing PbrProps.
+/// Stored as `u32` columns along X, indexed by `y + z * WIDTH` (so a voxel's column is its
+/// `LocalCoord::linearize` shifted down by `LN_SIZE`, and its bit is its X). A coarse level keeps
+/// one bit per 8x8x8 brick, set while any voxel in the brick is.
+#[derive(Clone, PartialEq, Eq)]
+pub struct OccupancyMask {
+    columns: Vec<u32>,
+    bricks: u64,
+}
+
+impl Default for OccupancyMask {
+    fn default() -> Self {
+        Self {
+            columns: vec![0; WIDTH * WIDTH],
+            bricks: 0,
+        }
+    }
+}
+
+impl OccupancyMask {
+    #[inline(always)]
+    pub fn get(&self, c: LocalCoord) -> bool {
+        self.columns[c.linearize() >> LN_SIZE] & (1 << c.0.x) != 0
+    }
+
+    pub fn set(&mut self, c: LocalCoord, value: bool) {
+        let column = &mut self.columns[c.linearize() >> LN_SIZE];
+        let brick = 1 << brick_index(c);
+
+        if value {
+            *column |= 1 << c.0.x;
+            self.bricks |= brick;
+        } else {
+            *column &= !(1 << c.0.x);
+            if !self.brick_has_any(c) {
+                self.bricks &= !brick;
+            }
+        }
+    }
+
+    /// The column of voxels along X at the given Y and Z, bit N being X = N.
+    #[inline(always)]
+    pub fn column(&self, y: u32, z: u32) -> u32 {
+        self.columns[(y + (z << LN_SIZE)) as usize]
+    }
+
+    /// Whether any voxel in the brick containing the coordinate is set.
+    #[inline(always)]
+    pub fn brick_occupied(&self, c: LocalCoord) -> bool {
+        self.bricks & (1 << brick_index(c)) != 0
+    }
+
+    /// Bytes of heap memory the mask uses.
+    pub fn memory_size(&self) -> usize {
+        self.columns.capacity() * std::mem::size_of::<u32>()
+    }
+
+    /// Whether every voxel is set.
+    pub fn is_full(&self) -> bool {
+        self.columns.iter().all(|column| *column == u32::MAX)
+    }
+
+    /// Scans the columns of the brick containing the coordinate, for when a voxel in it is cleared.
+    fn brick_has_any(&self, c: LocalCoord) -> bool {
+        let min = (c.0 >> BRICK_LN) << BRICK_LN;
+        let bits = (u8::MAX as u32) << min.x;
+
+        (min.z..min.z + BRICK_WIDTH)
+            .any(|z| (min.y..min.y + BRICK_WIDTH).any(|y| self.column(y, z) & bits != 0))
+    }
+}
+
+#[inline(always)]
+fn brick_index(c: LocalCoord) -> u32 {
+    let b = c.0 >> BRICK_LN;
+    b.x + (b.y + b.z * BRICKS_PER_AXIS) * BRICKS_PER_AXIS
+}
+
+/// An `OccupancyMask` of a chunk along with a 1 voxel border taken from its neighbors, which is
+/// everything face culling and AO of the chunk look at. Rows are `u64`s along X with bit N being
+/// X = N - 1, so shifting a row by one lines each voxel up with its -X or +X neighbor.
+pub struct PaddedOccupancy {
+    rows: Vec<u64>,
+}
+
+const PADDED_WIDTH: i32 = WIDTH as i32 + 2;
+
+/// The bits of a `PaddedOccupancy` row that belong to the chunk itself, rather than its border.
+pub const PADDED_INNER: u64 = (u32::MAX as u64) << 1;
+
+impl PaddedOccupancy {
+    /// Builds the padded occupancy of a chunk from the given mask of it and its neighbors. Missing
+    /// chunks are empty.
+    pub fn new<F>(buffer: &Buffer, chunk_coord: ChunkCoord, mask: F) -> Self
+    where
+        F: Fn(&Chunk) -> &OccupancyMask,
+    {
+        // The 3x3x3 neighborhood of chunks, indexed by offset + 1.
+        let mut neighbors = [[[None; 3]; 3]; 3];
+        for (z, plane) in neighbors.iter_mut().enumerate() {
+            for (y, row) in plane.iter_mut().enumerate() {
+                for (x, neighbor) in row.iter_mut().enumerate() {
+                    let offset = IVec3::new(x as i32, y as i32, z as i32) - IVec3::ONE;
+                    *neighbor = buffer
+                        .chunks
+                        .get(&ChunkCoord(chunk_coord.0 + offset))
+                        .map(&mask);
+                }
+            }
+        }
+
+        // Which chunk (as an index into `neighbors`) and which of its columns a padded Y or Z is.
+        let split = |v: i32| match v {
+            -1 => (0, WIDTH as u32 - 1),
+            v if v == WIDTH as i32 => (2, 0),
+            v => (1, v as u32),
+        };
+
+        let mut rows = Vec::with_capacity((PADDED_WIDTH * PADDED_WIDTH) as usize);
+        for z in -1..=WIDTH as i32 {
+            let (cz, lz) = split(z);
+            for y in -1..=WIDTH as i32 {
+                let (cy, ly) = split(y);
+                let column = |cx: usize| neighbors[cz][cy][cx].map_or(0, |m| m.column(ly, lz));
+
+                let left = (column(0) >> (WIDTH - 1)) as u64;
+                let center = (column(1) as u64) << 1;
+                let right = ((column(2) & 1) as u64) << (WIDTH + 1);
+                rows.push(left | center | right);
+            }
+        }
+
+        Self { rows }
+    }
+
+    /// The row at the given chunk-local Y and Z, each from -1 to `WIDTH`.
+    #[inline(always)]
+    pub fn row(&self, y: i32, z: i32) -> u64 {
+        self.rows[((y + 1) + (z + 1) * PADDED_WIDTH) as usize]
+    }
+
+    /// The bit of a chunk-local coordinate, from -1 to `WIDTH` on every axis.
+    #[inline(always)]
+    pub fn get(&self, c: IVec3) -> bool {
+        self.row(c.y, c.z) & (1 << (c.x + 1)) != 0
+    }
+
+    /// The row at the given Y and Z of the neighbors in direction `norm`, lined up with the voxels
+    /// of the row they neighbor.
+    #[inline(always)]
+    pub fn neighbor_row(&self, y: i32, z: i32, norm: IVec3) -> u64 {
+        let row = self.row(y + norm.y, z + norm.z);
+        match norm.x {
+            -1 => row << 1,
+            1 => row >> 1,
+            _ => row,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::voxel::{PbrProps, Rgba, WorldCoord};
+
+    use super::*;
+
+    #[test]
+    fn test_occupancy_mask() {
+        let mut mask = OccupancyMask::default();
+        let a = LocalCoord(UVec3::new(3, 9, 30));
+        let b = LocalCoord(UVec3::new(6, 10, 25));
+
+        mask.set(a, true);
+        mask.set(b, true);
+        assert!(mask.get(a));
+        assert_eq!(mask.column(9, 30), 1 << 3);
+        assert!(mask.brick_occupied(LocalCoord(UVec3::new(0, 8, 24))));
+        assert!(!mask.brick_occupied(LocalCoord(UVec3::new(8, 8, 24))));
+
+        // The brick stays occupied until its last voxel is cleared.
+        mask.set(a, false);
+        assert!(!mask.get(a));
+        assert!(mask.brick_occupied(a));
+        mask.set(b, false);
+        assert!(!mask.brick_occupied(a));
+    }
+
+    #[test]
+    fn test_padded_occupancy() {
+        let mut buffer = Buffer::default();
+        let p = PbrProps {
+            color: Rgba::from(Color::RED),
+            ..default()
+        };
+        buffer.set(WorldCoord(IVec3::new(0, 0, 0)), p);
+        buffer.set(WorldCoord(IVec3::new(-1, 0, 0)), p);
+        buffer.set(WorldCoord(IVec3::new(32, 5, 0)), p);
+        buffer.set(WorldCoord(IVec3::new(4, 32, 31)), p);
+
+        let padded = PaddedOccupancy::new(&buffer, ChunkCoord(IVec3::ZERO), |c| &c.occupied);
+        assert_eq!(padded.row(0, 0), 0b11);
+        assert_eq!(padded.row(5, 0), 1 << 33);
+        assert!(padded.get(IVec3::new(4, 32, 31)));
+        assert!(!padded.get(IVec3::new(4, 31, 31)));
+
+        // The -X neighbor of the voxel at X = 0 is the one at X = -1.
+        let row = padded.row(0, 0) & PADDED_INNER;
+        assert_eq!(row & padded.neighbor_row(0, 0, IVec3::NEG_X), 0b10);
+        assert_eq!(row & padded.neighbor_row(0, 0, IVec3::X), 0);
+    }
+}
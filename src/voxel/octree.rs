@@ -0,0 +1,482 @@
+use bevy::prelude::*;
+
+use super::{Buffer, PbrProps, RaycastOptions, VoxelRayHit, VoxelStorage, WorldCoord};
+
+/// Half the width of the region an empty `Octree` covers. It grows as needed.
+const INITIAL_HALF_WIDTH: i32 = 32;
+
+/// A sparse voxel octree, an alternative to `Buffer` for huge, mostly empty volumes. Uniform
+/// regions of any size (empty or not) are a single leaf, so empty space costs nothing to store and
+/// raycasts step over it in one go, where a `Buffer` has to allocate whole 32x32x32 chunks.
+///
+/// The tree covers a cube centered on the origin, doubling in size whenever a voxel is set outside
+/// of it (voxels further than 2^29 from the origin can't be set). Random access walks the tree
+/// from the root, so it's slower than a `Buffer` for editing.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Octree {
+    root: Node,
+
+    /// The tree covers `-half_width..half_width` on every axis.
+    half_width: i32,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Node {
+    /// A region where every voxel is the same.
+    Leaf(PbrProps),
+
+    /// A region split into 8 octants, indexed by `child_index`.
+    Branch(Box<[Node; 8]>),
+}
+
+impl Default for Octree {
+    fn default() -> Self {
+        Self {
+            root: Node::Leaf(default()),
+            half_width: INITIAL_HALF_WIDTH,
+        }
+    }
+}
+
+impl Octree {
+    /// The number of nodes in the tree, a rough measure of its memory use.
+    #[allow(dead_code)]
+    pub fn node_count(&self) -> usize {
+        self.root.count()
+    }
+
+    fn min(&self) -> IVec3 {
+        IVec3::splat(-self.half_width)
+    }
+
+    fn contains(&self, coord: IVec3) -> bool {
+        coord.cmpge(self.min()).all() && coord.cmplt(IVec3::splat(self.half_width)).all()
+    }
+
+    /// Every non-empty uniform region of the tree, as its min corner, width and voxel. The tree is
+    /// walked as the iterator is advanced.
+    pub fn regions(&self) -> impl Iterator<Item = (WorldCoord, i32, PbrProps)> + '_ {
+        let mut stack = vec![(&self.root, self.min(), self.half_width * 2)];
+
+        std::iter::from_fn(move || {
+            while let Some((node, min, width)) = stack.pop() {
+                match node {
+                    Node::Leaf(props) if props.is_empty() => {}
+                    Node::Leaf(props) => return Some((WorldCoord(min), width, *props)),
+                    Node::Branch(children) => {
+                        let width = width / 2;
+                        stack.extend(children.iter().enumerate().rev().map(|(index, child)| {
+                            (child, min + child_offset(index) * width, width)
+                        }));
+                    }
+                }
+            }
+
+            None
+        })
+    }
+
+    /// Doubles the covered region. Each octant of the root becomes the innermost octant of the
+    /// new octant on the same side of the origin. Returns false, leaving the tree as it is, if the
+    /// width of the region would overflow.
+    fn grow(&mut self) -> bool {
+        let half_width = match self.half_width.checked_mul(2) {
+            Some(half_width) if half_width.checked_mul(2).is_some() => half_width,
+            _ => return false,
+        };
+
+        let old = std::mem::replace(&mut self.root, Node::Leaf(default()));
+        let children = old.into_children();
+
+        let mut root: [Node; 8] = std::array::from_fn(|_| Node::Leaf(default()));
+        for (i, child) in children.into_iter().enumerate() {
+            let mut grandchildren: [Node; 8] = std::array::from_fn(|_| Node::Leaf(default()));
+            grandchildren[7 - i] = child;
+
+            root[i] = Node::Branch(Box::new(grandchildren));
+            root[i].collapse();
+        }
+
+        self.root = Node::Branch(Box::new(root));
+        self.root.collapse();
+        self.half_width = half_width;
+        true
+    }
+}
+
+impl VoxelStorage for Octree {
+    fn get(&self, coord: WorldCoord) -> PbrProps {
+        if !self.contains(coord.0) {
+            return default();
+        }
+
+        let mut node = &self.root;
+        let mut min = self.min();
+        let mut width = self.half_width * 2;
+
+        loop {
+            match node {
+                Node::Leaf(props) => return *props,
+                Node::Branch(children) => {
+                    width /= 2;
+                    let index = child_index(coord.0, min + width);
+                    min += child_offset(index) * width;
+                    node = &children[index];
+                }
+            }
+        }
+    }
+
+    fn set(&mut self, coord: WorldCoord, props: PbrProps) {
+        if !self.contains(coord.0) && props.is_empty() {
+            return;
+        }
+
+        while !self.contains(coord.0) {
+            if !self.grow() {
+                return;
+            }
+        }
+
+        let min = self.min();
+        let width = self.half_width * 2;
+        self.root.set(min, width, coord.0, props);
+    }
+
+    fn voxels(&self) -> Box<dyn Iterator<Item = (WorldCoord, PbrProps)> + '_> {
+        Box::new(self.regions().flat_map(|(min, width, props)| {
+            let max = WorldCoord(min.0 + IVec3::splat(width - 1));
+            WorldCoord::iter_range(min, max).map(move |coord| (coord, props))
+        }))
+    }
+
+    fn raycast(&self, ray: Ray, options: RaycastOptions) -> Option<VoxelRayHit> {
+        let (t_enter, _, axis) = ray_cube(ray, self.min(), self.half_width * 2)?;
+        self.root.raycast(
+            self.min(),
+            self.half_width * 2,
+            ray,
+            &options,
+            (t_enter, axis),
+        )
+    }
+}
+
+impl Node {
+    fn count(&self) -> usize {
+        match self {
+            Node::Leaf(_) => 1,
+            Node::Branch(children) => 1 + children.iter().map(Node::count).sum::<usize>(),
+        }
+    }
+
+    /// The 8 children of the node, splitting a leaf into 8 copies of itself.
+    fn into_children(self) -> [Node; 8] {
+        match self {
+            Node::Leaf(props) => std::array::from_fn(|_| Node::Leaf(props)),
+            Node::Branch(children) => *children,
+        }
+    }
+
+    /// Turns a branch back into a leaf if all its children are the same leaf.
+    fn collapse(&mut self) {
+        if let Node::Branch(children) = self {
+            if let Node::Leaf(first) = children[0] {
+                if children.iter().all(|child| *child == Node::Leaf(first)) {
+                    *self = Node::Leaf(first);
+                }
+            }
+        }
+    }
+
+    fn set(&mut self, min: IVec3, width: i32, coord: IVec3, props: PbrProps) {
+        if *self == Node::Leaf(props) {
+            return;
+        }
+
+        if width == 1 {
+            *self = Node::Leaf(props);
+            return;
+        }
+
+        if let Node::Leaf(_) = self {
+            let node = std::mem::replace(self, Node::Leaf(default()));
+            *self = Node::Branch(Box::new(node.into_children()));
+        }
+
+        if let Node::Branch(children) = self {
+            let width = width / 2;
+            let index = child_index(coord, min + width);
+            children[index].set(min + child_offset(index) * width, width, coord, props);
+        }
+
+        self.collapse();
+    }
+
+    /// The first voxel `options` allows where the ray crosses this node, which it enters at
+    /// `entry` (the distance along the ray, and the axis of the face it enters through).
+    fn raycast(
+        &self,
+        min: IVec3,
+        width: i32,
+        ray: Ray,
+        options: &RaycastOptions,
+        entry: (f32, usize),
+    ) -> Option<VoxelRayHit> {
+        let (t_enter, axis) = entry;
+        if t_enter > options.max_distance {
+            return None;
+        }
+
+        match self {
+            Node::Leaf(props) => {
+                if (props.is_empty() && !options.include_empty)
+                    || !options.filter.map_or(true, |filter| filter(props))
+                {
+                    return None;
+                }
+
+                // The entry point is on the face of the node, so floor can land just outside it.
+                let point = ray.origin + ray.direction * t_enter;
+                let max = min + IVec3::splat(width - 1);
+                let coord = point.floor().as_ivec3().clamp(min, max);
+
+                let normal = (t_enter > 0.0).then(|| {
+                    let mut normal = IVec3::ZERO;
+                    normal[axis] = -ray.direction[axis].signum() as i32;
+                    normal
+                });
+
+                Some(VoxelRayHit {
+                    world_coord: WorldCoord(coord),
+                    distance: t_enter,
+                    normal,
+                    props: *props,
+                })
+            }
+            Node::Branch(children) => {
+                // Children don't overlap, so visiting them in the order the ray enters them finds
+                // the nearest hit first.
+                let width = width / 2;
+                let mut crossed: Vec<_> = children
+                    .iter()
+                    .enumerate()
+                    .filter_map(|(index, child)| {
+                        if !options.include_empty && *child == Node::Leaf(default()) {
+                            return None;
+                        }
+
+                        let child_min = min + child_offset(index) * width;
+                        let (t_enter, _, axis) = ray_cube(ray, child_min, width)?;
+                        Some((t_enter, axis, child_min, child))
+                    })
+                    .collect();
+                crossed.sort_by(|a, b| a.0.total_cmp(&b.0));
+
+                crossed
+                    .into_iter()
+                    .find_map(|(t_enter, axis, child_min, child)| {
+                        child.raycast(child_min, width, ray, options, (t_enter, axis))
+                    })
+            }
+        }
+    }
+}
+
+/// The octant of `coord` around `center`: bit 0 set for +X, bit 1 for +Y and bit 2 for +Z.
+fn child_index(coord: IVec3, center: IVec3) -> usize {
+    let positive = coord.cmpge(center);
+    positive.x as usize | (positive.y as usize) << 1 | (positive.z as usize) << 2
+}
+
+/// The offset of the octant's min corner from its parent's, in units of the octant's width.
+fn child_offset(index: usize) -> IVec3 {
+    IVec3::new(
+        (index & 1) as i32,
+        (index >> 1 & 1) as i32,
+        (index >> 2 & 1) as i32,
+    )
+}
+
+/// Slab test of a ray against a cube. Returns where the ray enters it (0 if it starts inside),
+/// where it leaves it and the axis of the face it enters through, or None if it misses.
+fn ray_cube(ray: Ray, min: IVec3, width: i32) -> Option<(f32, f32, usize)> {
+    let min = min.as_vec3();
+    let max = min + Vec3::splat(width as f32);
+    let mut near = f32::NEG_INFINITY;
+    let mut far = f32::INFINITY;
+    let mut axis = 0;
+
+    for i in 0..3 {
+        if ray.direction[i].abs() < f32::EPSILON {
+            // Parallel to this axis' planes, so the ray has to be between them the whole time.
+            if ray.origin[i] < min[i] || ray.origin[i] >= max[i] {
+                return None;
+            }
+            continue;
+        }
+
+        let t1 = (min[i] - ray.origin[i]) / ray.direction[i];
+        let t2 = (max[i] - ray.origin[i]) / ray.direction[i];
+        let (t1, t2) = (t1.min(t2), t1.max(t2));
+
+        if t1 > near {
+            near = t1;
+            axis = i;
+        }
+        far = far.min(t2);
+    }
+
+    if near >= far || far <= 0.0 {
+        return None;
+    }
+
+    Some((near.max(0.0), far, axis))
+}
+
+impl From<&Buffer> for Octree {
+    fn from(buffer: &Buffer) -> Self {
+        buffer.convert()
+    }
+}
+
+impl From<&Octree> for Buffer {
+    fn from(octree: &Octree) -> Self {
+        octree.convert()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::voxel::{raycast_buffer_voxels, Rgba};
+
+    use super::*;
+
+    fn props(color: Color) -> PbrProps {
+        PbrProps {
+            color: Rgba::from(color),
+            ..default()
+        }
+    }
+
+    #[test]
+    fn test_octree_get_set() {
+        let mut octree = Octree::default();
+        let red = props(Color::RED);
+
+        octree.set(WorldCoord(IVec3::new(3, -7, 12)), red);
+        assert_eq!(octree.get(WorldCoord(IVec3::new(3, -7, 12))), red);
+        assert!(octree.get(WorldCoord(IVec3::new(3, -7, 13))).is_empty());
+
+        // Far outside the initial bounds, so the tree grows around what's already there.
+        octree.set(WorldCoord(IVec3::new(-1000, 5000, 7)), red);
+        assert_eq!(octree.get(WorldCoord(IVec3::new(-1000, 5000, 7))), red);
+        assert_eq!(octree.get(WorldCoord(IVec3::new(3, -7, 12))), red);
+
+        // An aligned 2x2x2 block of the same voxel collapses into a single leaf.
+        octree.set(WorldCoord(IVec3::ZERO), red);
+        let single = octree.node_count();
+        for coord in WorldCoord::iter_range((0, 0, 0).into(), (1, 1, 1).into()) {
+            octree.set(coord, red);
+        }
+        assert_eq!(octree.node_count(), single - 8);
+        assert_eq!(octree.voxels().count(), 10);
+
+        // Clearing everything collapses back to an empty root.
+        for (coord, _) in octree.voxels().collect::<Vec<_>>() {
+            octree.set(coord, default());
+        }
+        assert_eq!(octree.node_count(), 1);
+    }
+
+    #[test]
+    fn test_octree_regions() {
+        let mut octree = Octree::default();
+        let red = props(Color::RED);
+
+        // A uniform region is a single leaf, and only expanded into voxels on demand.
+        for coord in WorldCoord::iter_range((0, 0, 0).into(), (15, 15, 15).into()) {
+            octree.set(coord, red);
+        }
+        octree.set(WorldCoord(IVec3::new(-1, 0, 0)), red);
+        let regions: Vec<_> = octree.regions().collect();
+        assert_eq!(regions.len(), 2);
+        assert!(regions.contains(&(WorldCoord(IVec3::ZERO), 16, red)));
+        assert_eq!(octree.voxels().count(), 16 * 16 * 16 + 1);
+    }
+
+    #[test]
+    fn test_octree_refuses_overflow() {
+        let mut octree = Octree::default();
+        let red = props(Color::RED);
+        octree.set(WorldCoord(IVec3::new(3, -7, 12)), red);
+
+        let far = WorldCoord(IVec3::new(i32::MAX, 0, 0));
+        octree.set(far, red);
+        assert!(octree.get(far).is_empty());
+        assert_eq!(octree.get(WorldCoord(IVec3::new(3, -7, 12))), red);
+
+        // The furthest voxel that fits still can be set.
+        let edge = WorldCoord(IVec3::new(-(1 << 29), 0, (1 << 29) - 1));
+        octree.set(edge, red);
+        assert_eq!(octree.get(edge), red);
+    }
+
+    #[test]
+    fn test_octree_conversion() {
+        let mut buffer = Buffer::default();
+        buffer.set(WorldCoord(IVec3::new(1, 2, 3)), props(Color::RED));
+        buffer.set(WorldCoord(IVec3::new(-40, 2, 90)), props(Color::BLUE));
+
+        let octree = Octree::from(&buffer);
+        assert_eq!(
+            octree.get(WorldCoord(IVec3::new(-40, 2, 90))),
+            props(Color::BLUE)
+        );
+
+        let round_trip = Buffer::from(&octree);
+        assert!(round_trip.changed_chunks(&buffer).is_empty());
+    }
+
+    #[test]
+    fn test_octree_raycast() {
+        let mut buffer = Buffer::default();
+        for coord in WorldCoord::iter_range((0, 0, 0).into(), (7, 0, 7).into()) {
+            buffer.set(coord, props(Color::RED));
+        }
+        buffer.set(WorldCoord(IVec3::new(3, 5, 3)), props(Color::BLUE));
+        let octree = Octree::from(&buffer);
+
+        let rays = [
+            Ray {
+                origin: Vec3::new(3.5, 20.0, 3.5),
+                direction: Vec3::NEG_Y,
+            },
+            Ray {
+                origin: Vec3::new(-10.0, 8.0, 4.2),
+                direction: Vec3::new(1.0, -0.6, 0.1).normalize(),
+            },
+            Ray {
+                origin: Vec3::new(5.5, 0.5, 5.5),
+                direction: Vec3::X,
+            },
+        ];
+
+        for ray in rays {
+            let expected = raycast_buffer_voxels(&buffer, ray).unwrap();
+            let hit = octree.raycast(ray, default()).unwrap();
+            assert_eq!(hit.world_coord, expected.world_coord);
+            assert_eq!(hit.normal, expected.normal);
+            assert!((hit.distance - expected.distance).abs() < 1e-4);
+        }
+
+        // The blue voxel is in the way of the first ray, unless only red voxels are hit.
+        let red = |p: &PbrProps| p.color == Rgba::from(Color::RED);
+        let options = RaycastOptions {
+            filter: Some(&red),
+            ..default()
+        };
+        let hit = octree.raycast(rays[0], options).unwrap();
+        assert_eq!(hit.world_coord, WorldCoord(IVec3::new(3, 0, 3)));
+    }
+}
@@ -0,0 +1,161 @@
+use bevy::prelude::*;
+
+use super::{raycast_buffer_voxels, Buffer, ChunkCoord, VoxelRayHit};
+
+/// The nearest voxel hit by `pick_voxel`, across all the buffers it was given.
+#[derive(Debug, Clone, Copy)]
+pub struct VoxelPick {
+    /// The entity the buffer that was hit belongs to.
+    pub entity: Entity,
+
+    /// The hit, in the local space of the entity.
+    pub ray_hit: VoxelRayHit,
+
+    /// The ray, in the local space of the entity.
+    pub local_ray: Ray,
+
+    /// The distance along the world space ray at which the hit occurred.
+    pub distance: f32,
+}
+
+/// Raycasts the buffers of every given entity with a world space ray, returning the nearest hit.
+/// Entities are first culled (and sorted) by a world space AABB of their buffer, so only those the
+/// ray could hit are raycast voxel by voxel, nearest first.
+pub fn pick_voxel<'a, I>(world_ray: Ray, entities: I) -> Option<VoxelPick>
+where
+    I: IntoIterator<Item = (Entity, &'a GlobalTransform, &'a Buffer)>,
+{
+    let mut candidates: Vec<_> = entities
+        .into_iter()
+        .filter_map(|(entity, transform, buffer)| {
+            let (min, max) = world_aabb(buffer, transform)?;
+            let distance = ray_aabb_distance(world_ray, min, max)?;
+            Some((distance, entity, transform, buffer))
+        })
+        .collect();
+    candidates.sort_by(|a, b| a.0.total_cmp(&b.0));
+
+    let mut nearest: Option<VoxelPick> = None;
+
+    for (aabb_distance, entity, transform, buffer) in candidates {
+        // Everything from here on starts further away than the nearest hit.
+        if nearest.map_or(false, |pick| pick.distance < aabb_distance) {
+            break;
+        }
+
+        let local_ray = {
+            let inverse = transform.affine().inverse();
+            let origin = inverse.transform_point3(world_ray.origin);
+            let direction = inverse.transform_vector3(world_ray.direction).normalize();
+            Ray { origin, direction }
+        };
+
+        let ray_hit = match raycast_buffer_voxels(buffer, local_ray) {
+            Some(ray_hit) => ray_hit,
+            None => continue,
+        };
+
+        // Local distances are scaled by the entity's transform, so measure in world space.
+        let local_point = local_ray.origin + local_ray.direction * ray_hit.distance;
+        let distance = (transform.transform_point(local_point) - world_ray.origin).length();
+
+        if nearest.map_or(true, |pick| distance < pick.distance) {
+            nearest = Some(VoxelPick {
+                entity,
+                ray_hit,
+                local_ray,
+                distance,
+            });
+        }
+    }
+
+    nearest
+}
+
+/// The world space bounds of all the chunks of the buffer, or None if it has no chunks.
+fn world_aabb(buffer: &Buffer, transform: &GlobalTransform) -> Option<(Vec3, Vec3)> {
+    let mut chunk_coords = buffer.chunks.keys();
+    let first = chunk_coords.next()?;
+    let (mut local_min, mut local_max) = (first.0, first.0);
+    for chunk_coord in chunk_coords {
+        local_min = local_min.min(chunk_coord.0);
+        local_max = local_max.max(chunk_coord.0);
+    }
+
+    let local_min = ChunkCoord(local_min).first_cell_coord().0.as_vec3();
+    let local_max = (ChunkCoord(local_max).last_cell_coord().0 + IVec3::ONE).as_vec3();
+
+    let mut min = Vec3::splat(f32::INFINITY);
+    let mut max = Vec3::splat(f32::NEG_INFINITY);
+    for i in 0..8 {
+        let corner = Vec3::select(
+            BVec3::new(i & 1 != 0, i & 2 != 0, i & 4 != 0),
+            local_max,
+            local_min,
+        );
+        let corner = transform.transform_point(corner);
+        min = min.min(corner);
+        max = max.max(corner);
+    }
+
+    Some((min, max))
+}
+
+/// Slab test of a ray against an AABB. Returns the distance along the ray at which it enters the
+/// box (0 if it starts inside), or None if it misses.
+fn ray_aabb_distance(ray: Ray, min: Vec3, max: Vec3) -> Option<f32> {
+    let inverse = ray.direction.recip();
+    let t1 = (min - ray.origin) * inverse;
+    let t2 = (max - ray.origin) * inverse;
+
+    let near = t1.min(t2).max_element().max(0.0);
+    let far = t1.max(t2).min_element();
+
+    (near <= far).then_some(near)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::voxel::{PbrProps, Rgba, WorldCoord};
+
+    use super::*;
+
+    #[test]
+    fn test_pick_voxel() {
+        let mut buffer = Buffer::default();
+        buffer.set(
+            WorldCoord(IVec3::ZERO),
+            PbrProps {
+                color: Rgba::from(Color::RED),
+                ..default()
+            },
+        );
+
+        let behind = GlobalTransform::from(Transform::from_xyz(0.0, 0.0, 10.0));
+        let front = GlobalTransform::from(Transform::from_xyz(0.0, 0.0, 20.0));
+        let beside = GlobalTransform::from(Transform::from_xyz(100.0, 0.0, 0.0));
+        let entities = [
+            (Entity::from_raw(0), &behind, &buffer),
+            (Entity::from_raw(1), &beside, &buffer),
+            (Entity::from_raw(2), &front, &buffer),
+        ];
+
+        // Looking down -Z at the voxel at the origin of each entity. The front one is hit on its +Z
+        // face, at z = 21.
+        let ray = Ray {
+            origin: Vec3::new(0.5, 0.5, 50.0),
+            direction: Vec3::NEG_Z,
+        };
+
+        let pick = pick_voxel(ray, entities).unwrap();
+        assert_eq!(pick.entity, Entity::from_raw(2));
+        assert_eq!(pick.ray_hit.world_coord, WorldCoord(IVec3::ZERO));
+        assert!((pick.distance - 29.0).abs() < 1e-4);
+
+        let miss = Ray {
+            origin: Vec3::new(50.0, 0.5, 50.0),
+            direction: Vec3::NEG_Z,
+        };
+        assert!(pick_voxel(miss, entities).is_none());
+    }
+}
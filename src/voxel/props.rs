@@ -8,6 +8,67 @@ pub struct PbrProps {
     pub roughness: u8,
     pub reflectance: u8,
     pub emission: u8,
+
+    /// Missing from voxels saved before patterns existed, which read as `DetailPattern::None`.
+    #[serde(default)]
+    pub pattern: DetailPattern,
+}
+
+impl PbrProps {
+    /// Empty voxels are all zero.
+    pub fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+
+    /// Opacity is stored in the color's alpha channel. Fully opaque voxels hide their neighbors.
+    pub fn is_opaque(&self) -> bool {
+        self.color.a == u8::MAX
+    }
+
+    /// Non-empty voxels that are less than fully opaque, like glass or water.
+    pub fn is_translucent(&self) -> bool {
+        !self.is_opaque() && !self.is_empty()
+    }
+
+    /// The largest difference between a channel of the two, or the maximum if their detail
+    /// patterns differ. Small differences make for similar looking materials.
+    pub fn difference(&self, other: &Self) -> u8 {
+        if self.pattern != other.pattern {
+            return u8::MAX;
+        }
+
+        let channels = |p: &Self| {
+            [
+                p.color.r,
+                p.color.g,
+                p.color.b,
+                p.color.a,
+                p.metallic,
+                p.roughness,
+                p.reflectance,
+                p.emission,
+            ]
+        };
+
+        channels(self)
+            .into_iter()
+            .zip(channels(other))
+            .map(|(a, b)| a.abs_diff(b))
+            .max()
+            .unwrap_or_default()
+    }
+}
+
+/// Surface detail of a voxel, drawn as a normal map tiled once across each face. Only blocky meshes
+/// have face UVs, so smooth meshes ignore it.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum DetailPattern {
+    #[default]
+    None,
+    /// An inset panel, from `textures/normal.png`.
+    Panel,
+    /// Rounded edges, from `textures/normal_round.png`.
+    Bevel,
 }
 
 #[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
@@ -22,27 +83,6 @@ impl Rgba {
     pub fn to_arr(&self) -> [u8; 4] {
         [self.r, self.g, self.b, self.a]
     }
-
-    pub fn shadow(&self, sa: bool) -> Self {
-        if sa {
-            let mut r = self.r as f32 / u8::MAX as f32;
-            let mut g = self.g as f32 / u8::MAX as f32;
-            let mut b = self.b as f32 / u8::MAX as f32;
-
-            r = r / 2.0;
-            g = g / 2.0;
-            b = b / 2.0;
-
-            Rgba {
-                r: f32::round(r * u8::MAX as f32) as u8,
-                g: f32::round(g * u8::MAX as f32) as u8,
-                b: f32::round(b * u8::MAX as f32) as u8,
-                a: self.a,
-            }
-        } else {
-            return self.clone();
-        }
-    }
 }
 
 impl From<Rgba> for Color {
@@ -61,3 +101,19 @@ impl From<Color> for Rgba {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_deserialize_without_pattern() {
+        let props: PbrProps = ron::from_str(
+            "(color: (r: 255, g: 0, b: 0, a: 255), metallic: 1, roughness: 2, reflectance: 3, \
+             emission: 4)",
+        )
+        .unwrap();
+        assert_eq!(props.pattern, DetailPattern::None);
+        assert_eq!(props.emission, 4);
+    }
+}
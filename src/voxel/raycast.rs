@@ -1,7 +1,9 @@
 use bevy::prelude::*;
 use ordered_float::OrderedFloat;
 
-use super::{Buffer, Chunk, ChunkCoord, LocalCoord, WorldCoord, WIDTH};
+use super::{
+    Buffer, Chunk, ChunkCoord, LocalCoord, PbrProps, WorldCoord, BRICK_LN, BRICK_WIDTH, WIDTH,
+};
 
 #[derive(Debug, Clone, Copy)]
 pub struct VoxelRayHit {
@@ -15,21 +17,70 @@ pub struct VoxelRayHit {
     /// The normal of the voxel face that was hit. This is None if the hit is within the voxel
     /// itself.
     pub normal: Option<IVec3>,
+
+    /// The voxel that was hit.
+    pub props: PbrProps,
 }
 
-pub fn raycast_buffer_voxels(buffer: &Buffer, ray: Ray) -> Option<VoxelRayHit> {
-    let chunk_ray_hits = raycast_chunk_coords(buffer, ray);
-
-    for chunk_ray_hit in chunk_ray_hits {
-        if let Some(voxel_hit) = raycast_chunk_voxels(
-            buffer.chunks.get(&chunk_ray_hit.chunk_coord).unwrap(),
-            chunk_ray_hit,
-        ) {
-            return Some(voxel_hit);
+/// Options for `raycast_buffer_voxels_with` and `raycast_buffer_voxels_all`.
+#[derive(Clone, Copy)]
+pub struct RaycastOptions<'a> {
+    /// Voxels further than this along the ray aren't hit.
+    pub max_distance: f32,
+
+    /// Only voxels this returns true for are hit. For example `PbrProps::is_opaque` to see through
+    /// glass.
+    pub filter: Option<&'a dyn Fn(&PbrProps) -> bool>,
+
+    /// Hit empty voxels too (before `filter`), so every voxel the ray passes through is hit. Only
+    /// voxels within the buffer's chunks are, space between chunks is skipped. Ignored by sweeps.
+    pub include_empty: bool,
+}
+
+impl Default for RaycastOptions<'_> {
+    fn default() -> Self {
+        Self {
+            max_distance: f32::INFINITY,
+            filter: None,
+            include_empty: false,
         }
     }
+}
 
-    None
+/// The first non-empty voxel along the ray.
+pub fn raycast_buffer_voxels(buffer: &Buffer, ray: Ray) -> Option<VoxelRayHit> {
+    raycast_buffer_voxels_with(buffer, ray, RaycastOptions::default())
+}
+
+/// The first voxel along the ray that `options` allows.
+pub fn raycast_buffer_voxels_with(
+    buffer: &Buffer,
+    ray: Ray,
+    options: RaycastOptions,
+) -> Option<VoxelRayHit> {
+    raycast_buffer_voxels_all(buffer, ray, options).next()
+}
+
+/// Every voxel along the ray that `options` allows, nearest first. That's only the non-empty ones,
+/// unless `options.include_empty` is set. Voxels are only walked as the iterator is advanced, so
+/// stopping early is cheap.
+pub fn raycast_buffer_voxels_all<'a>(
+    buffer: &'a Buffer,
+    ray: Ray,
+    options: RaycastOptions<'a>,
+) -> impl Iterator<Item = VoxelRayHit> + 'a {
+    // Chunks don't overlap, so hits in chunks sorted by where the ray enters them are sorted too.
+    raycast_chunk_coords(buffer, ray)
+        .into_iter()
+        .flat_map(move |chunk_ray_hit| {
+            ChunkVoxelWalk::new(
+                buffer.chunks.get(&chunk_ray_hit.chunk_coord).unwrap(),
+                chunk_ray_hit,
+                options.include_empty,
+            )
+        })
+        .take_while(move |hit| hit.distance <= options.max_distance)
+        .filter(move |hit| options.filter.map_or(true, |filter| filter(&hit.props)))
 }
 
 #[derive(Debug)]
@@ -67,7 +118,7 @@ fn aabb_test_chunk_coord(chunk_coord: ChunkCoord, ray: Ray) -> Option<ChunkRayHi
     let mut t = Vec3::ZERO;
 
     let Ray { origin, direction } = ray;
-    let origin_i = origin.as_ivec3();
+    let origin_i = origin.floor().as_ivec3();
 
     // If the origin is withing the chunk's bounds, then we return a hit at the origin point.
     if origin_i.cmpge(min_i).all() && origin_i.cmplt(max_i).all() {
@@ -82,8 +133,11 @@ fn aabb_test_chunk_coord(chunk_coord: ChunkCoord, ray: Ray) -> Option<ChunkRayHi
     for i in 0..3 {
         if direction[i] > 0.0 {
             t[i] = (min[i] - origin[i]) / direction[i];
-        } else {
+        } else if direction[i] < 0.0 {
             t[i] = (max[i] - origin[i]) / direction[i];
+        } else {
+            // Parallel to this axis' planes, so they can't be the ones the ray enters through.
+            t[i] = f32::NEG_INFINITY;
         }
     }
 
@@ -125,120 +179,338 @@ fn aabb_test_chunk_coord(chunk_coord: ChunkCoord, ray: Ray) -> Option<ChunkRayHi
     return None;
 }
 
-fn raycast_chunk_voxels(chunk: &Chunk, chunk_ray_hit: ChunkRayHit) -> Option<VoxelRayHit> {
-    let p = chunk_ray_hit.origin;
-    let d = chunk_ray_hit.direction;
-    let mut norm: Option<IVec3> = None;
+/// Walks the cells a ray crosses (Amanatides & Woo), in order, without bounds. Yields each cell
+/// with the distance along the ray at which the walk entered it.
+pub(super) struct CellWalk {
+    /// The current cell.
+    i: IVec3,
 
-    // How long we have traveled thus far (modified by initial 'jump to volume bounds').
-    let mut t = f32::from(chunk_ray_hit.t);
+    /// The normal of the face the walk entered the current cell through.
+    norm: Option<IVec3>,
 
-    // Max distance we can travel. This is the corner to corner distance of the chunk, plus 2.
-    let max_d = t + Vec3::splat(WIDTH as f32).length() + 2.0;
+    /// How far the walk has traveled from the ray origin.
+    t: f32,
 
-    // The starting voxel for the raycast.
-    let mut i = p.floor().as_ivec3();
+    /// The direction we are stepping for each component.
+    step: IVec3,
 
-    // The directionVec we are stepping for each component.
-    let step = d.signum().as_ivec3();
+    /// Distance along the ray between cell boundaries, for each component.
+    delta: Vec3,
 
-    // Just abs(Vec3::ONE / d) but accounts for zeros in the distance vector.
-    let delta = (Vec3::new(
-        if d.x.abs() < f32::EPSILON {
-            f32::INFINITY
-        } else {
-            1.0 / d.x
-        },
-        if d.y.abs() < f32::EPSILON {
-            f32::INFINITY
-        } else {
-            1.0 / d.y
-        },
-        if d.z.abs() < f32::EPSILON {
-            f32::INFINITY
-        } else {
-            1.0 / d.z
-        },
-    ))
-    .abs();
-
-    let dist = Vec3::new(
-        if step.x > 0 {
-            i.x as f32 + 1.0 - p.x
-        } else {
-            p.x - i.x as f32
-        },
-        if step.y > 0 {
-            i.y as f32 + 1.0 - p.y
-        } else {
-            p.y - i.y as f32
-        },
-        if step.z > 0 {
-            i.z as f32 + 1.0 - p.z
-        } else {
-            p.z - i.z as f32
-        },
-    );
-
-    // The nearest voxel boundary.
-    let mut t_max = Vec3::new(
-        if delta.x < f32::INFINITY {
-            delta.x * dist.x
-        } else {
-            f32::INFINITY
-        },
-        if delta.y < f32::INFINITY {
-            delta.y * dist.y
-        } else {
-            f32::INFINITY
-        },
-        if delta.z < f32::INFINITY {
-            delta.z * dist.z
-        } else {
-            f32::INFINITY
-        },
-    );
-
-    while t <= max_d {
-        // Test if the current traverse is within the volume, and the voxel isn't empty.
-        if i.cmpge(IVec3::ZERO).all()
-            && i.cmplt(IVec3::splat(WIDTH as i32)).all()
-            && chunk.get(LocalCoord(i.as_uvec3())) != default()
-        {
-            return Some(VoxelRayHit {
-                world_coord: WorldCoord(chunk_ray_hit.chunk_coord.first_cell_coord().0 + i),
-                distance: t,
-                normal: norm.map(|n| n.into()),
-            });
+    /// Distance (from the ray origin) to the nearest cell boundary, for each component.
+    t_max: Vec3,
+}
+
+impl CellWalk {
+    pub(super) fn new(p: Vec3, d: Vec3) -> Self {
+        // The starting cell for the walk.
+        let i = p.floor().as_ivec3();
+
+        let step = d.signum().as_ivec3();
+
+        // Just abs(Vec3::ONE / d) but accounts for zeros in the distance vector.
+        let delta = Vec3::select(
+            d.abs().cmplt(Vec3::splat(f32::EPSILON)),
+            Vec3::splat(f32::INFINITY),
+            d.recip(),
+        )
+        .abs();
+
+        let dist = Vec3::select(
+            step.cmpgt(IVec3::ZERO),
+            i.as_vec3() + Vec3::ONE - p,
+            p - i.as_vec3(),
+        );
+
+        // The nearest cell boundary.
+        let t_max = Vec3::select(
+            delta.cmplt(Vec3::splat(f32::INFINITY)),
+            delta * dist,
+            Vec3::splat(f32::INFINITY),
+        );
+
+        Self {
+            i,
+            norm: None,
+            t: 0.0,
+            step,
+            delta,
+            t_max,
         }
+    }
+
+    /// Moves to the next cell along the ray, across the nearest cell boundary.
+    fn advance(&mut self) {
+        let axis = if self.t_max.x < self.t_max.y {
+            if self.t_max.x < self.t_max.z {
+                0
+            } else {
+                2
+            }
+        } else if self.t_max.y < self.t_max.z {
+            1
+        } else {
+            2
+        };
+
+        self.i[axis] += self.step[axis];
+        self.t = self.t_max[axis];
+        self.t_max[axis] += self.delta[axis];
+
+        let mut norm = IVec3::ZERO;
+        norm[axis] = -self.step[axis];
+        self.norm = Some(norm);
+    }
+
+    /// Moves to the first cell along the ray outside of the brick of the current cell, in one
+    /// jump. Equivalent to calling `advance` until then.
+    fn skip_brick(&mut self) {
+        let brick_min = (self.i >> BRICK_LN) << BRICK_LN;
+
+        // How many cell boundaries the walk crosses on each axis before leaving the brick on that
+        // axis, and when the last of them is.
+        let steps = IVec3::select(
+            self.step.cmpgt(IVec3::ZERO),
+            brick_min + IVec3::splat(BRICK_WIDTH as i32) - self.i,
+            self.i - brick_min + IVec3::ONE,
+        );
+        // Axes the ray is parallel to are never crossed (and infinity times 0 would be NaN).
+        let exits = Vec3::select(
+            self.delta.cmpeq(Vec3::splat(f32::INFINITY)),
+            Vec3::splat(f32::INFINITY),
+            self.t_max + self.delta * (steps - IVec3::ONE).as_vec3(),
+        );
 
-        // Select the smallest t_max
-        if t_max.x < t_max.y {
-            if t_max.x < t_max.z {
-                i.x += step.x;
-                t = t_max.x;
-                t_max.x += delta.x;
-                norm = Some(IVec3::new(-step.x, 0, 0));
+        let axis = if exits.x < exits.y {
+            if exits.x < exits.z {
+                0
             } else {
-                i.z += step.z;
-                t = t_max.z;
-                t_max.z += delta.z;
-                norm = Some(IVec3::new(0, 0, -step.z));
+                2
             }
+        } else if exits.y < exits.z {
+            1
         } else {
-            if t_max.y < t_max.z {
-                i.y += step.y;
-                t = t_max.y;
-                t_max.y += delta.y;
-                norm = Some(IVec3::new(0, -step.y, 0));
+            2
+        };
+        let t_exit = exits[axis];
+
+        for a in 0..3 {
+            // The boundaries crossed before the exit. Other axes stay within the brick.
+            let crossed = if a == axis {
+                steps[a]
+            } else if self.t_max[a] >= t_exit {
+                0
             } else {
-                i.z += step.z;
-                t = t_max.z;
-                t_max.z += delta.z;
-                norm = Some(IVec3::new(0, 0, -step.z));
+                (((t_exit - self.t_max[a]) / self.delta[a]) as i32 + 1).min(steps[a] - 1)
+            };
+
+            if crossed == 0 {
+                continue;
             }
+
+            self.i[a] += self.step[a] * crossed;
+            self.t_max[a] += self.delta[a] * crossed as f32;
         }
+
+        self.t = t_exit;
+
+        let mut norm = IVec3::ZERO;
+        norm[axis] = -self.step[axis];
+        self.norm = Some(norm);
     }
+}
 
-    return None;
+impl Iterator for CellWalk {
+    type Item = (IVec3, f32);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let cell = (self.i, self.t);
+        self.advance();
+        Some(cell)
+    }
+}
+
+/// Walks the voxels of a single chunk along a ray, yielding the non-empty ones (or all of them
+/// with `include_empty`).
+struct ChunkVoxelWalk<'a> {
+    chunk: &'a Chunk,
+    chunk_coord: ChunkCoord,
+    include_empty: bool,
+
+    /// The walk, in chunk-local space. Its current cell may be outside the chunk.
+    walk: CellWalk,
+
+    /// The distance along the ray to the start of the walk (modified by the initial 'jump to
+    /// volume bounds').
+    t_start: f32,
+
+    /// Max distance we can travel. This is the corner to corner distance of the chunk, plus 2.
+    t_end: f32,
+
+    /// Set once the walk has been inside the chunk. Leaving it again ends the walk.
+    entered: bool,
+}
+
+impl<'a> ChunkVoxelWalk<'a> {
+    fn new(chunk: &'a Chunk, chunk_ray_hit: ChunkRayHit, include_empty: bool) -> Self {
+        Self {
+            chunk,
+            chunk_coord: chunk_ray_hit.chunk_coord,
+            include_empty,
+            walk: CellWalk::new(chunk_ray_hit.origin, chunk_ray_hit.direction),
+            t_start: chunk_ray_hit.t.0,
+            t_end: Vec3::splat(WIDTH as f32).length() + 2.0,
+            entered: false,
+        }
+    }
+}
+
+impl Iterator for ChunkVoxelWalk<'_> {
+    type Item = VoxelRayHit;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while self.walk.t <= self.t_end {
+            let i = self.walk.i;
+
+            // Test if the current traverse is within the volume, and the voxel isn't empty (unless
+            // empty voxels are wanted too).
+            if i.cmpge(IVec3::ZERO).all() && i.cmplt(IVec3::splat(WIDTH as i32)).all() {
+                self.entered = true;
+
+                let local_coord = LocalCoord(i.as_uvec3());
+                if !self.include_empty && !self.chunk.occupied.brick_occupied(local_coord) {
+                    self.walk.skip_brick();
+                    continue;
+                }
+
+                let (t, norm) = (self.walk.t, self.walk.norm);
+                self.walk.advance();
+
+                if self.include_empty || self.chunk.occupied.get(local_coord) {
+                    return Some(VoxelRayHit {
+                        world_coord: WorldCoord(self.chunk_coord.first_cell_coord().0 + i),
+                        distance: self.t_start + t,
+                        normal: norm,
+                        props: self.chunk.get(local_coord),
+                    });
+                }
+            } else if self.entered {
+                return None;
+            } else {
+                self.walk.advance();
+            }
+        }
+
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::voxel::Rgba;
+
+    use super::*;
+
+    #[test]
+    fn test_raycast_options() {
+        let mut buffer = Buffer::default();
+        let solid = PbrProps {
+            color: Rgba::from(Color::RED),
+            ..default()
+        };
+        let glass = PbrProps {
+            color: Rgba::from(Color::rgba(1.0, 1.0, 1.0, 0.5)),
+            ..default()
+        };
+
+        buffer.set(WorldCoord(IVec3::new(2, 0, 0)), glass);
+        buffer.set(WorldCoord(IVec3::new(5, 0, 0)), solid);
+        buffer.set(WorldCoord(IVec3::new(40, 0, 0)), solid);
+
+        // Starts outside of any chunk, so distances include the jump to the first one.
+        let ray = Ray {
+            origin: Vec3::new(-10.0, 0.5, 0.5),
+            direction: Vec3::X,
+        };
+
+        let hits: Vec<_> = raycast_buffer_voxels_all(&buffer, ray, default())
+            .map(|hit| (hit.world_coord.0.x, hit.distance))
+            .collect();
+        assert_eq!(hits, vec![(2, 12.0), (5, 15.0), (40, 50.0)]);
+
+        let hit = raycast_buffer_voxels(&buffer, ray).unwrap();
+        assert_eq!(hit.props, glass);
+        assert_eq!(hit.normal, Some(IVec3::NEG_X));
+
+        let opaque = RaycastOptions {
+            filter: Some(&|p: &PbrProps| p.is_opaque()),
+            ..default()
+        };
+        let hit = raycast_buffer_voxels_with(&buffer, ray, opaque).unwrap();
+        assert_eq!(hit.world_coord, WorldCoord(IVec3::new(5, 0, 0)));
+
+        let short = RaycastOptions {
+            max_distance: 14.0,
+            ..opaque
+        };
+        assert!(raycast_buffer_voxels_with(&buffer, ray, short).is_none());
+    }
+
+    #[test]
+    fn test_raycast_include_empty() {
+        let mut buffer = Buffer::default();
+        let p = PbrProps {
+            color: Rgba::from(Color::RED),
+            ..default()
+        };
+        buffer.set(WorldCoord(IVec3::new(3, 0, 0)), p);
+
+        let ray = Ray {
+            origin: Vec3::new(0.5, 0.5, 0.5),
+            direction: Vec3::X,
+        };
+        let options = RaycastOptions {
+            include_empty: true,
+            ..default()
+        };
+
+        let hits: Vec<_> = raycast_buffer_voxels_all(&buffer, ray, options)
+            .take(5)
+            .map(|hit| (hit.world_coord.0.x, hit.props.is_empty()))
+            .collect();
+        assert_eq!(
+            hits,
+            vec![(0, true), (1, true), (2, true), (3, false), (4, true)]
+        );
+
+        // The walk ends where the only chunk does.
+        assert_eq!(
+            raycast_buffer_voxels_all(&buffer, ray, options).count(),
+            WIDTH
+        );
+    }
+
+    #[test]
+    fn test_raycast_skips_empty_bricks() {
+        let mut buffer = Buffer::default();
+        let p = PbrProps {
+            color: Rgba::from(Color::RED),
+            ..default()
+        };
+        buffer.set(WorldCoord(IVec3::new(27, 19, 6)), p);
+        buffer.set(WorldCoord(IVec3::new(30, 30, 30)), p);
+
+        // A diagonal ray crossing several empty bricks on the way to each voxel.
+        for origin in [Vec3::new(0.2, 0.7, 0.4), Vec3::new(-5.0, 3.0, 12.0)] {
+            let target = Vec3::new(27.5, 19.5, 6.5);
+            let ray = Ray {
+                origin,
+                direction: (target - origin).normalize(),
+            };
+
+            let hit = raycast_buffer_voxels(&buffer, ray).unwrap();
+            assert_eq!(hit.world_coord, WorldCoord(IVec3::new(27, 19, 6)));
+            assert!(hit.distance <= (target - origin).length());
+        }
+    }
 }
@@ -0,0 +1,217 @@
+use bevy::{
+    pbr::{
+        CubemapVisibleEntities, DrawShadowMesh, ExtractedDirectionalLight, ExtractedPointLight,
+        LightEntity, NotShadowCaster, Shadow, ShadowPipeline, ShadowPipelineKey, ViewLightEntities,
+        SHADOW_FORMAT,
+    },
+    prelude::*,
+    render::{
+        mesh::MeshVertexBufferLayout,
+        render_asset::RenderAssets,
+        render_phase::{DrawFunctions, RenderPhase},
+        render_resource::{
+            BindGroupLayout, CompareFunction, DepthBiasState, DepthStencilState, FrontFace,
+            MultisampleState, PipelineCache, PolygonMode, PrimitiveState, RenderPipelineDescriptor,
+            SpecializedMeshPipeline, SpecializedMeshPipelineError, SpecializedMeshPipelines,
+            StencilFaceState, StencilState, VertexState,
+        },
+        view::VisibleEntities,
+        Extract, RenderApp, RenderStage,
+    },
+};
+
+use super::{VoxelChunk, ATTRIBUTE_PACKED};
+
+/// Draws blocky chunks into Bevy's shadow maps. Bevy's own shadow pipeline needs a
+/// `Mesh::ATTRIBUTE_POSITION`, which blocky meshes don't have, so they're kept out of its queue
+/// and drawn with a pipeline that decodes `ATTRIBUTE_PACKED` instead. Must be added after the
+/// `PbrPlugin`.
+pub struct VoxelShadowPlugin;
+
+impl Plugin for VoxelShadowPlugin {
+    fn build(&self, app: &mut App) {
+        let render_app = ok_or_return!(app.get_sub_app_mut(RenderApp));
+        render_app
+            .init_resource::<VoxelShadowPipeline>()
+            .init_resource::<SpecializedMeshPipelines<VoxelShadowPipeline>>()
+            .add_system_to_stage(RenderStage::Extract, extract_voxel_shadow_casters)
+            .add_system_to_stage(RenderStage::Queue, queue_voxel_shadows);
+    }
+}
+
+/// Marks blocky chunks in the render world. They're also marked `NotShadowCaster`, which is what
+/// keeps them out of Bevy's shadow queue.
+#[derive(Component)]
+struct VoxelShadowCaster;
+
+/// The same as Bevy's `ShadowPipeline`, except for the vertex shader and its input.
+#[derive(Resource)]
+struct VoxelShadowPipeline {
+    shader: Handle<Shader>,
+    view_layout: BindGroupLayout,
+    mesh_layout: BindGroupLayout,
+}
+
+impl FromWorld for VoxelShadowPipeline {
+    fn from_world(world: &mut World) -> Self {
+        let shadow_pipeline = world.resource::<ShadowPipeline>();
+        let view_layout = shadow_pipeline.view_layout.clone();
+        let mesh_layout = shadow_pipeline.mesh_layout.clone();
+
+        Self {
+            shader: world
+                .resource::<AssetServer>()
+                .load("shaders/voxel_shadow.wgsl"),
+            view_layout,
+            mesh_layout,
+        }
+    }
+}
+
+impl SpecializedMeshPipeline for VoxelShadowPipeline {
+    type Key = ShadowPipelineKey;
+
+    fn specialize(
+        &self,
+        key: Self::Key,
+        layout: &MeshVertexBufferLayout,
+    ) -> Result<RenderPipelineDescriptor, SpecializedMeshPipelineError> {
+        let vertex_buffer_layout = layout.get_layout(&[ATTRIBUTE_PACKED.at_shader_location(0)])?;
+
+        Ok(RenderPipelineDescriptor {
+            vertex: VertexState {
+                shader: self.shader.clone(),
+                entry_point: "vertex".into(),
+                shader_defs: vec![],
+                buffers: vec![vertex_buffer_layout],
+            },
+            fragment: None,
+            layout: Some(vec![self.view_layout.clone(), self.mesh_layout.clone()]),
+            primitive: PrimitiveState {
+                topology: key.primitive_topology(),
+                strip_index_format: None,
+                front_face: FrontFace::Ccw,
+                cull_mode: None,
+                unclipped_depth: false,
+                polygon_mode: PolygonMode::Fill,
+                conservative: false,
+            },
+            depth_stencil: Some(DepthStencilState {
+                format: SHADOW_FORMAT,
+                depth_write_enabled: true,
+                depth_compare: CompareFunction::GreaterEqual,
+                stencil: StencilState {
+                    front: StencilFaceState::IGNORE,
+                    back: StencilFaceState::IGNORE,
+                    read_mask: 0,
+                    write_mask: 0,
+                },
+                bias: DepthBiasState {
+                    constant: 0,
+                    slope_scale: 0.0,
+                    clamp: 0.0,
+                },
+            }),
+            multisample: MultisampleState::default(),
+            label: Some("voxel_shadow_pipeline".into()),
+        })
+    }
+}
+
+/// Marks the visible chunks with packed meshes, the same ones Bevy extracts meshes for.
+#[allow(clippy::type_complexity)]
+fn extract_voxel_shadow_casters(
+    mut commands: Commands,
+    meshes: Extract<Res<Assets<Mesh>>>,
+    chunks: Extract<
+        Query<
+            (Entity, &ComputedVisibility, &Handle<Mesh>),
+            (With<VoxelChunk>, Without<NotShadowCaster>),
+        >,
+    >,
+) {
+    let casters: Vec<_> = chunks
+        .iter()
+        .filter(|(_, visibility, mesh)| {
+            visibility.is_visible()
+                && meshes
+                    .get(mesh)
+                    .map_or(false, |mesh| mesh.attribute(ATTRIBUTE_PACKED).is_some())
+        })
+        .map(|(entity, ..)| (entity, (VoxelShadowCaster, NotShadowCaster)))
+        .collect();
+    commands.insert_or_spawn_batch(casters);
+}
+
+/// Bevy's `queue_shadows`, for blocky chunks.
+#[allow(clippy::too_many_arguments)]
+fn queue_voxel_shadows(
+    shadow_draw_functions: Res<DrawFunctions<Shadow>>,
+    voxel_shadow_pipeline: Res<VoxelShadowPipeline>,
+    casters: Query<&Handle<Mesh>, With<VoxelShadowCaster>>,
+    render_meshes: Res<RenderAssets<Mesh>>,
+    mut pipelines: ResMut<SpecializedMeshPipelines<VoxelShadowPipeline>>,
+    mut pipeline_cache: ResMut<PipelineCache>,
+    view_lights: Query<&ViewLightEntities>,
+    mut view_light_shadow_phases: Query<(&LightEntity, &mut RenderPhase<Shadow>)>,
+    point_light_entities: Query<&CubemapVisibleEntities, With<ExtractedPointLight>>,
+    directional_light_entities: Query<&VisibleEntities, With<ExtractedDirectionalLight>>,
+    spot_light_entities: Query<&VisibleEntities, With<ExtractedPointLight>>,
+) {
+    let draw_shadow_mesh = shadow_draw_functions
+        .read()
+        .get_id::<DrawShadowMesh>()
+        .unwrap();
+
+    for view_lights in &view_lights {
+        for view_light_entity in view_lights.lights.iter().copied() {
+            let (light_entity, mut shadow_phase) =
+                view_light_shadow_phases.get_mut(view_light_entity).unwrap();
+            let visible_entities = match light_entity {
+                LightEntity::Directional { light_entity } => {
+                    ok_or_return!(directional_light_entities.get(*light_entity))
+                }
+                LightEntity::Point {
+                    light_entity,
+                    face_index,
+                } => ok_or_return!(point_light_entities.get(*light_entity)).get(*face_index),
+                LightEntity::Spot { light_entity } => {
+                    ok_or_return!(spot_light_entities.get(*light_entity))
+                }
+            };
+
+            for entity in visible_entities.iter().copied() {
+                let mesh_handle = match casters.get(entity) {
+                    Ok(mesh_handle) => mesh_handle,
+                    Err(_) => continue,
+                };
+                let mesh = match render_meshes.get(mesh_handle) {
+                    Some(mesh) => mesh,
+                    None => continue,
+                };
+
+                let key = ShadowPipelineKey::from_primitive_topology(mesh.primitive_topology);
+                let pipeline = pipelines.specialize(
+                    &mut pipeline_cache,
+                    &voxel_shadow_pipeline,
+                    key,
+                    &mesh.layout,
+                );
+                let pipeline = match pipeline {
+                    Ok(pipeline) => pipeline,
+                    Err(error) => {
+                        error!("{}", error);
+                        continue;
+                    }
+                };
+
+                shadow_phase.add(Shadow {
+                    draw_function: draw_shadow_mesh,
+                    pipeline,
+                    entity,
+                    distance: 0.0,
+                });
+            }
+        }
+    }
+}
@@ -0,0 +1,56 @@
+use bevy::prelude::*;
+
+use super::{
+    raycast_buffer_voxels_with, Buffer, PbrProps, RaycastOptions, VoxelRayHit, WorldCoord,
+};
+
+/// The read/write surface shared by voxel storage backends, so code that only reads and writes
+/// voxels doesn't care whether they're in a `Buffer` or an `Octree`.
+pub trait VoxelStorage {
+    /// The voxel at the coordinate, empty if nothing was set there.
+    fn get(&self, coord: WorldCoord) -> PbrProps;
+
+    /// Sets the voxel at the coordinate. Setting it to empty removes it.
+    fn set(&mut self, coord: WorldCoord, props: PbrProps);
+
+    /// Every non-empty voxel, in no particular order.
+    fn voxels(&self) -> Box<dyn Iterator<Item = (WorldCoord, PbrProps)> + '_>;
+
+    /// The first voxel along the ray that `options` allows.
+    fn raycast(&self, ray: Ray, options: RaycastOptions) -> Option<VoxelRayHit>;
+
+    /// Copies every voxel into a new storage of another kind.
+    fn convert<S>(&self) -> S
+    where
+        S: VoxelStorage + Default,
+    {
+        let mut storage = S::default();
+        for (coord, props) in self.voxels() {
+            storage.set(coord, props);
+        }
+        storage
+    }
+}
+
+impl VoxelStorage for Buffer {
+    fn get(&self, coord: WorldCoord) -> PbrProps {
+        Buffer::get(self, coord)
+    }
+
+    fn set(&mut self, coord: WorldCoord, props: PbrProps) {
+        Buffer::set(self, coord, props)
+    }
+
+    fn voxels(&self) -> Box<dyn Iterator<Item = (WorldCoord, PbrProps)> + '_> {
+        Box::new(self.chunks.iter().flat_map(|(chunk_coord, chunk)| {
+            chunk_coord
+                .iter_world_coords()
+                .filter(|coord| chunk.occupied.get((*coord).into()))
+                .map(|coord| (coord, chunk.get(coord)))
+        }))
+    }
+
+    fn raycast(&self, ray: Ray, options: RaycastOptions) -> Option<VoxelRayHit> {
+        raycast_buffer_voxels_with(self, ray, options)
+    }
+}
@@ -0,0 +1,324 @@
+use bevy::{
+    prelude::*,
+    render::{
+        mesh::{Indices, VertexAttributeValues},
+        render_resource::PrimitiveTopology,
+    },
+};
+
+use super::{
+    Buffer, ChunkCoord, FastBufferReader, MeshLayer, PbrProps, WorldCoord, ATTRIBUTE_AO,
+    ATTRIBUTE_COLOR_EMISSIVE, ATTRIBUTE_OPACITY, ATTRIBUTE_PBR_NORM, WIDTH,
+};
+
+const W: i32 = WIDTH as i32;
+
+/// Width of the sampled voxel region, starting 3 below the chunk. Cells reach 2 below the chunk
+/// (for the faces of its lowest voxels), their corners' densities are blurred over neighbors, and
+/// the high side needs one voxel past the chunk for the same reasons.
+const SAMPLES: i32 = W + 5;
+
+/// Width of the grid of densities (one per voxel center), starting 2 below the chunk.
+const GRID: i32 = W + 3;
+
+/// Width of the grid of cells between densities, starting 2 below the chunk.
+const CELLS: i32 = W + 2;
+
+/// Meshes a single layer of a chunk as a smooth surface using Surface Nets, with vertex positions
+/// relative to `MeshLayer::chunk_origin`. PbrProps are averaged from the solid voxels around each
+/// vertex, and normals come from the density gradient. Returns None if there is nothing to draw.
+///
+/// Inside/outside is exactly voxel occupancy, so the surface has the same topology as the blocky
+/// mesh; only the placement of vertices is smoothed, by blurring occupancy into a density field.
+/// Each face belongs to the chunk of its solid voxel, and vertices are derived from the same
+/// samples on both sides of a chunk boundary, so meshes of adjacent chunks are seamless.
+pub fn mesh_chunk_smooth(
+    buffer: &Buffer,
+    chunk_coord: ChunkCoord,
+    layer: MeshLayer,
+) -> Option<Mesh> {
+    let min = chunk_coord.first_cell_coord().0;
+    let max = chunk_coord.last_cell_coord().0;
+    let origin = layer.chunk_origin(chunk_coord);
+    let mut reader = FastBufferReader::new(buffer);
+
+    // Linear index into a cube of the given width, starting at `start` (all in world space).
+    let index = |start: IVec3, width: i32, c: IVec3| {
+        let p = c - start;
+        (p.x + (p.y + p.z * width) * width) as usize
+    };
+
+    let sample_start = min - IVec3::splat(3);
+    let mut voxels = vec![PbrProps::default(); (SAMPLES * SAMPLES * SAMPLES) as usize];
+    let mut solid = vec![false; voxels.len()];
+
+    for WorldCoord(c) in WorldCoord::iter_range(
+        WorldCoord(sample_start),
+        WorldCoord(sample_start + IVec3::splat(SAMPLES - 1)),
+    ) {
+        let props = reader.get(WorldCoord(c));
+        solid[index(sample_start, SAMPLES, c)] = layer.contains(&props);
+        voxels[index(sample_start, SAMPLES, c)] = props;
+    }
+
+    // Densities at voxel centers. Half occupancy, half the 3x3x3 average, so that `d > 0.5`
+    // exactly when the voxel is solid, but iso crossings are pulled toward solid neighbors.
+    let grid_start = min - IVec3::splat(2);
+    let mut density = vec![0.0f32; (GRID * GRID * GRID) as usize];
+
+    for WorldCoord(c) in WorldCoord::iter_range(
+        WorldCoord(grid_start),
+        WorldCoord(grid_start + IVec3::splat(GRID - 1)),
+    ) {
+        let blur = WorldCoord::iter_range(WorldCoord(c - IVec3::ONE), WorldCoord(c + IVec3::ONE))
+            .filter(|n| solid[index(sample_start, SAMPLES, n.0)])
+            .count() as f32
+            / 27.0;
+        let occupancy = if solid[index(sample_start, SAMPLES, c)] {
+            1.0
+        } else {
+            0.0
+        };
+        density[index(grid_start, GRID, c)] = 0.5 * occupancy + 0.5 * blur;
+    }
+
+    let inside = |c: IVec3| density[index(grid_start, GRID, c)] > 0.5;
+
+    let mut builder = SmoothMeshBuilder::default();
+
+    // One vertex per cell that the surface passes through. Cells are identified by the voxel
+    // center at their lowest corner.
+    let mut cell_verts = vec![None; (CELLS * CELLS * CELLS) as usize];
+
+    for WorldCoord(c) in WorldCoord::iter_range(
+        WorldCoord(grid_start),
+        WorldCoord(grid_start + IVec3::splat(CELLS - 1)),
+    ) {
+        let corners = CORNERS.map(|o| density[index(grid_start, GRID, c + o)]);
+        let corners_inside = corners.map(|d| d > 0.5);
+
+        if corners_inside.iter().all(|i| *i) || corners_inside.iter().all(|i| !*i) {
+            continue;
+        }
+
+        // Average the iso crossings of all edges with a sign change.
+        let mut offset = Vec3::ZERO;
+        let mut crossings = 0;
+        for (a, b) in EDGES {
+            if corners_inside[a] != corners_inside[b] {
+                let t = (0.5 - corners[a]) / (corners[b] - corners[a]);
+                let pa = CORNERS[a].as_vec3();
+                let pb = CORNERS[b].as_vec3();
+                offset += pa + (pb - pa) * t;
+                crossings += 1;
+            }
+        }
+        offset /= crossings as f32;
+
+        // Density increases inward, so the outward normal is the negative gradient.
+        let mut gradient = Vec3::ZERO;
+        for (o, d) in CORNERS.iter().zip(corners) {
+            gradient += (o.as_vec3() * 2.0 - Vec3::ONE) * d;
+        }
+        let normal = (-gradient).try_normalize().unwrap_or(Vec3::Y);
+
+        // Average the props of the solid voxels at the corners of the cell.
+        let props = CORNERS
+            .iter()
+            .zip(corners_inside)
+            .filter(|(_, inside)| *inside)
+            .map(|(o, _)| voxels[index(sample_start, SAMPLES, c + *o)]);
+
+        let position = (c - origin).as_vec3() + Vec3::splat(0.5) + offset;
+        cell_verts[index(grid_start, CELLS, c)] =
+            Some(builder.push_vertex(position, normal, props));
+    }
+
+    // Emit a quad for every face of every solid voxel in the chunk, joining the vertices of the 4
+    // cells around the edge between the voxel and its empty neighbor.
+    for WorldCoord(v) in WorldCoord::iter_range(WorldCoord(min), WorldCoord(max)) {
+        if !inside(v) {
+            continue;
+        }
+
+        for (axis, tan, bi_tan) in AXES {
+            for (lo, outward) in [(v, axis), (v - axis, -axis)] {
+                if inside(v + outward) {
+                    continue;
+                }
+
+                let quad = [lo - tan - bi_tan, lo - bi_tan, lo, lo - tan]
+                    .map(|cell| cell_verts[index(grid_start, CELLS, cell)]);
+
+                if let [Some(a), Some(b), Some(c), Some(d)] = quad {
+                    // Counter-clockwise when viewed from the outward side.
+                    if outward == axis {
+                        builder.indexes.extend([a, b, c, a, c, d]);
+                    } else {
+                        builder.indexes.extend([a, c, b, a, d, c]);
+                    }
+                }
+            }
+        }
+    }
+
+    if builder.indexes.is_empty() {
+        None
+    } else {
+        Some(builder.build())
+    }
+}
+
+/// The 8 corners of a cell.
+const CORNERS: [IVec3; 8] = [
+    IVec3::new(0, 0, 0),
+    IVec3::new(1, 0, 0),
+    IVec3::new(0, 1, 0),
+    IVec3::new(1, 1, 0),
+    IVec3::new(0, 0, 1),
+    IVec3::new(1, 0, 1),
+    IVec3::new(0, 1, 1),
+    IVec3::new(1, 1, 1),
+];
+
+/// The 12 edges of a cell, as pairs of indexes into `CORNERS`.
+const EDGES: [(usize, usize); 12] = [
+    (0, 1),
+    (2, 3),
+    (4, 5),
+    (6, 7),
+    (0, 2),
+    (1, 3),
+    (4, 6),
+    (5, 7),
+    (0, 4),
+    (1, 5),
+    (2, 6),
+    (3, 7),
+];
+
+/// Right handed tuples of (axis, tangent, bi-tangent).
+const AXES: [(IVec3, IVec3, IVec3); 3] = [
+    (IVec3::X, IVec3::Y, IVec3::Z),
+    (IVec3::Y, IVec3::Z, IVec3::X),
+    (IVec3::Z, IVec3::X, IVec3::Y),
+];
+
+#[derive(Default)]
+struct SmoothMeshBuilder {
+    positions: Vec<[f32; 3]>,
+    normals: Vec<[f32; 3]>,
+    pbr_norm: Vec<[u8; 4]>,
+    color_emissive: Vec<[u8; 4]>,
+    opacity: Vec<f32>,
+    indexes: Vec<u32>,
+}
+
+impl SmoothMeshBuilder {
+    fn push_vertex(
+        &mut self,
+        position: Vec3,
+        normal: Vec3,
+        props: impl Iterator<Item = PbrProps>,
+    ) -> u32 {
+        // Sums of r, g, b, a, metallic, roughness, reflectance, emission.
+        let mut sums = [0u32; 8];
+        let mut count = 0;
+        for p in props {
+            let channels = [
+                p.color.r,
+                p.color.g,
+                p.color.b,
+                p.color.a,
+                p.metallic,
+                p.roughness,
+                p.reflectance,
+                p.emission,
+            ];
+            for (sum, channel) in sums.iter_mut().zip(channels) {
+                *sum += channel as u32;
+            }
+            count += 1;
+        }
+        let [r, g, b, a, metallic, roughness, reflectance, emission] =
+            sums.map(|s| (s / count.max(1)) as u8);
+
+        self.positions.push(position.to_array());
+        self.normals.push(normal.to_array());
+        self.pbr_norm.push([metallic, roughness, reflectance, 0]);
+        self.color_emissive.push([r, g, b, emission]);
+        self.opacity.push(a as f32 / u8::MAX as f32);
+
+        (self.positions.len() - 1) as u32
+    }
+
+    fn build(self) -> Mesh {
+        let mut mesh = Mesh::new(PrimitiveTopology::TriangleList);
+        let vertex_count = self.positions.len();
+
+        mesh.insert_attribute(Mesh::ATTRIBUTE_POSITION, self.positions);
+        mesh.insert_attribute(Mesh::ATTRIBUTE_NORMAL, self.normals);
+        mesh.insert_attribute(
+            ATTRIBUTE_PBR_NORM,
+            VertexAttributeValues::Uint8x4(self.pbr_norm),
+        );
+        mesh.insert_attribute(
+            ATTRIBUTE_COLOR_EMISSIVE,
+            VertexAttributeValues::Unorm8x4(self.color_emissive),
+        );
+        // Smooth surfaces have no hard corners to occlude.
+        mesh.insert_attribute(
+            ATTRIBUTE_AO,
+            VertexAttributeValues::Uint32(vec![3; vertex_count]),
+        );
+        mesh.insert_attribute(ATTRIBUTE_OPACITY, self.opacity);
+        mesh.set_indices(Some(Indices::U32(self.indexes)));
+
+        mesh
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::voxel::Rgba;
+
+    use super::*;
+
+    #[test]
+    fn test_single_voxel() {
+        let mut buffer = Buffer::default();
+        let p = PbrProps {
+            color: Rgba::from(Color::RED),
+            ..default()
+        };
+        buffer.set(WorldCoord(IVec3::new(4, 4, 4)), p);
+
+        let chunk_coord = ChunkCoord(IVec3::ZERO);
+        let mesh = mesh_chunk_smooth(&buffer, chunk_coord, MeshLayer::Opaque).unwrap();
+
+        // A single voxel is wrapped by the 8 cells around its center, 6 quads in total.
+        assert_eq!(mesh.count_vertices(), 8);
+        assert_eq!(mesh.indices().unwrap().len(), 6 * 6);
+        assert!(mesh_chunk_smooth(&buffer, chunk_coord, MeshLayer::Translucent).is_none());
+    }
+
+    #[test]
+    fn test_chunk_boundary() {
+        let mut buffer = Buffer::default();
+        let p = PbrProps {
+            color: Rgba::from(Color::RED),
+            ..default()
+        };
+
+        // Faces belong to the chunk of their voxel, even on chunk boundaries.
+        buffer.set(WorldCoord(IVec3::new(0, 4, 4)), p);
+
+        let quads = |chunk_coord| {
+            mesh_chunk_smooth(&buffer, ChunkCoord(chunk_coord), MeshLayer::Opaque)
+                .map_or(0, |m| m.indices().unwrap().len() / 6)
+        };
+
+        assert_eq!(quads(IVec3::ZERO), 6);
+        assert_eq!(quads(IVec3::NEG_X), 0);
+    }
+}
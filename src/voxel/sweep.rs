@@ -0,0 +1,422 @@
+use bevy::prelude::*;
+
+use super::{Buffer, CellWalk, FastBufferReader, LocalCoord, PbrProps, RaycastOptions, WorldCoord};
+
+/// Shapes closer than this to a voxel are touching it, not overlapping it. Keeps shapes resting on
+/// (or sliding along) a surface from hitting it.
+const SKIN: f32 = 1e-4;
+
+/// A hit of `sweep_aabb` or `sweep_sphere`.
+#[derive(Debug, Clone, Copy)]
+pub struct VoxelSweepHit {
+    /// The distance the shape travels along the direction before it touches the voxel. 0 if it
+    /// already overlaps it.
+    pub time_of_impact: f32,
+
+    /// The normal of the voxel's surface at the contact, pointing out of the voxel.
+    pub normal: Vec3,
+
+    /// The voxel that was hit.
+    pub world_coord: WorldCoord,
+    pub props: PbrProps,
+}
+
+/// Sweeps an axis aligned box (in the local space of the buffer) along `direction`, returning the
+/// first voxel it touches. `direction` must be normalized, `options.max_distance` is how far to
+/// sweep.
+#[allow(dead_code)]
+pub fn sweep_aabb(
+    buffer: &Buffer,
+    center: Vec3,
+    half_extents: Vec3,
+    direction: Vec3,
+    options: RaycastOptions,
+) -> Option<VoxelSweepHit> {
+    sweep(buffer, center, half_extents, direction, options, |voxel| {
+        // The box hits the voxel when its center hits the voxel grown by its half extents.
+        let half = Vec3::splat(0.5);
+        let (t, axis) = ray_box(center - voxel, direction, half + half_extents)?;
+        let mut normal = Vec3::ZERO;
+        normal[axis] = -direction[axis].signum();
+        Some((t, normal))
+    })
+}
+
+/// Sweeps a sphere (in the local space of the buffer) along `direction`, returning the first voxel
+/// it touches. `direction` must be normalized, `options.max_distance` is how far to sweep.
+#[allow(dead_code)]
+pub fn sweep_sphere(
+    buffer: &Buffer,
+    center: Vec3,
+    radius: f32,
+    direction: Vec3,
+    options: RaycastOptions,
+) -> Option<VoxelSweepHit> {
+    sweep(
+        buffer,
+        center,
+        Vec3::splat(radius),
+        direction,
+        options,
+        |voxel| ray_rounded_box(center - voxel, direction, Vec3::splat(0.5), radius),
+    )
+}
+
+/// Every non-empty voxel overlapping the axis aligned box (in the local space of the buffer).
+#[allow(dead_code)]
+pub fn overlap_aabb(
+    buffer: &Buffer,
+    center: Vec3,
+    half_extents: Vec3,
+) -> Vec<(WorldCoord, PbrProps)> {
+    let half = Vec3::splat(0.5) + half_extents - SKIN;
+    voxels_in_bounds(buffer, center - half_extents, center + half_extents)
+        .filter(|(world_coord, _)| {
+            let voxel = world_coord.0.as_vec3() + Vec3::splat(0.5);
+            (center - voxel).abs().cmplt(half).all()
+        })
+        .collect()
+}
+
+/// Every non-empty voxel overlapping the sphere (in the local space of the buffer).
+#[allow(dead_code)]
+pub fn overlap_sphere(buffer: &Buffer, center: Vec3, radius: f32) -> Vec<(WorldCoord, PbrProps)> {
+    voxels_in_bounds(
+        buffer,
+        center - Vec3::splat(radius),
+        center + Vec3::splat(radius),
+    )
+    .filter(|(world_coord, _)| {
+        let min = world_coord.0.as_vec3();
+        let closest = center.clamp(min, min + Vec3::ONE);
+        closest.distance(center) < radius - SKIN
+    })
+    .collect()
+}
+
+/// Finds the voxel with the earliest time of impact. Walks the cells the center of the shape
+/// crosses, testing every voxel the shape can overlap while its center is in the cell, until no
+/// later cell can hold an earlier hit. `impact` gets the center of a voxel and returns the time of
+/// impact and normal.
+fn sweep<F>(
+    buffer: &Buffer,
+    center: Vec3,
+    half_extents: Vec3,
+    direction: Vec3,
+    options: RaycastOptions,
+    impact: F,
+) -> Option<VoxelSweepHit>
+where
+    F: Fn(Vec3) -> Option<(f32, Vec3)>,
+{
+    if buffer.chunks.is_empty() {
+        return None;
+    }
+
+    // Past where the center leaves the chunks (grown by the shape) there's nothing to hit, which
+    // also ends sweeps with an infinite `max_distance`.
+    let (min_chunk, max_chunk) = buffer.chunk_aabb();
+    let min = min_chunk.first_cell_coord().0.as_vec3() - half_extents;
+    let max = (max_chunk.last_cell_coord().0 + IVec3::ONE).as_vec3() + half_extents;
+    let mut t_end = options.max_distance;
+    for i in 0..3 {
+        if direction[i].abs() < f32::EPSILON {
+            if center[i] < min[i] || center[i] > max[i] {
+                return None;
+            }
+            continue;
+        }
+
+        let bound = if direction[i] > 0.0 { max[i] } else { min[i] };
+        t_end = t_end.min((bound - center[i]) / direction[i]);
+    }
+
+    let mut reader = FastBufferReader::new(buffer);
+    let mut nearest: Option<VoxelSweepHit> = None;
+
+    for (cell, t) in CellWalk::new(center, direction) {
+        // Hits from this cell on are at least `t` away.
+        if t > t_end || nearest.map_or(false, |hit| hit.time_of_impact <= t) {
+            break;
+        }
+
+        let cell = cell.as_vec3();
+        let start = (cell - half_extents).floor().as_ivec3();
+        let end = (cell + Vec3::ONE + half_extents).ceil().as_ivec3() - IVec3::ONE;
+
+        for world_coord in WorldCoord::iter_range(WorldCoord(start), WorldCoord(end)) {
+            let props = reader.get(world_coord);
+            if props.is_empty() || !options.filter.map_or(true, |filter| filter(&props)) {
+                continue;
+            }
+
+            let (time_of_impact, normal) = match impact(world_coord.0.as_vec3() + Vec3::splat(0.5))
+            {
+                Some(impact) => impact,
+                None => continue,
+            };
+            if time_of_impact > options.max_distance
+                || nearest.map_or(false, |hit| hit.time_of_impact <= time_of_impact)
+            {
+                continue;
+            }
+
+            nearest = Some(VoxelSweepHit {
+                time_of_impact,
+                normal,
+                world_coord,
+                props,
+            });
+        }
+    }
+
+    nearest
+}
+
+/// Non-empty voxels that overlap the (finite) bounds, only visiting chunks that exist.
+fn voxels_in_bounds(
+    buffer: &Buffer,
+    min: Vec3,
+    max: Vec3,
+) -> impl Iterator<Item = (WorldCoord, PbrProps)> + '_ {
+    buffer.chunks.iter().flat_map(move |(chunk_coord, chunk)| {
+        let first = chunk_coord.first_cell_coord().0;
+        let last = chunk_coord.last_cell_coord().0;
+
+        let start = min
+            .floor()
+            .clamp(first.as_vec3(), last.as_vec3() + 1.0)
+            .as_ivec3();
+        let end = (max.ceil() - 1.0)
+            .clamp(first.as_vec3() - 1.0, last.as_vec3())
+            .as_ivec3();
+
+        (start.z..=end.z).flat_map(move |z| {
+            (start.y..=end.y).flat_map(move |y| {
+                (start.x..=end.x).filter_map(move |x| {
+                    let coord = IVec3::new(x, y, z);
+                    let props = chunk.get(LocalCoord((coord - first).as_uvec3()));
+                    (!props.is_empty()).then_some((WorldCoord(coord), props))
+                })
+            })
+        })
+    })
+}
+
+/// Slab test of a ray (relative to the center of a box) against the box. Returns the distance to
+/// where the ray enters the box and the axis it enters through, 0 if it starts inside. Rays that
+/// only touch the box, or start touching it and move away, miss.
+fn ray_box(origin: Vec3, direction: Vec3, half: Vec3) -> Option<(f32, usize)> {
+    let mut near = f32::NEG_INFINITY;
+    let mut far = f32::INFINITY;
+    let mut axis = 0;
+
+    for i in 0..3 {
+        if direction[i].abs() < f32::EPSILON {
+            // Parallel to this axis' planes, so the ray has to be between them the whole time.
+            if origin[i].abs() >= half[i] - SKIN {
+                return None;
+            }
+            continue;
+        }
+
+        let t1 = (-half[i] - origin[i]) / direction[i];
+        let t2 = (half[i] - origin[i]) / direction[i];
+        let (t1, t2) = (t1.min(t2), t1.max(t2));
+
+        if t1 > near {
+            near = t1;
+            axis = i;
+        }
+        far = far.min(t2);
+    }
+
+    if near >= far - SKIN || far <= SKIN {
+        return None;
+    }
+
+    Some((near.max(0.0), axis))
+}
+
+/// Intersects a ray (relative to the center of a box) with the box grown by `radius`, with rounded
+/// edges and corners. That is where a sphere of `radius` moving along the ray first touches the box.
+/// Returns the distance and the normal of the box at the contact.
+fn ray_rounded_box(origin: Vec3, direction: Vec3, half: Vec3, radius: f32) -> Option<(f32, Vec3)> {
+    let normal_at = |t: f32| {
+        let p = origin + direction * t;
+        (p - p.clamp(-half, half)).normalize_or_zero()
+    };
+
+    // Already overlapping.
+    if (origin - origin.clamp(-half, half)).length() < radius - SKIN {
+        return Some((0.0, normal_at(0.0)));
+    }
+
+    let (t, _) = ray_box(origin, direction, half + radius)?;
+
+    // Outside the box on at most one axis means the ray entered through a flat face.
+    let p = origin + direction * t;
+    let outside = p.abs().cmpgt(half);
+    if [outside.x, outside.y, outside.z]
+        .iter()
+        .filter(|o| **o)
+        .count()
+        <= 1
+    {
+        return Some((t, normal_at(t)));
+    }
+
+    // Otherwise it's an edge or the corner of the octant the ray entered through. Mirror
+    // everything into the positive octant so that's always the corner at `half`.
+    let s = Vec3::select(p.cmplt(Vec3::ZERO), Vec3::NEG_ONE, Vec3::ONE);
+    let o = origin * s - half;
+    let d = direction * s;
+    let r2 = radius * radius;
+    let mut nearest = f32::INFINITY;
+
+    // The corner sphere.
+    let b = o.dot(d);
+    let h = b * b - (o.dot(o) - r2);
+    if h > 0.0 {
+        nearest = -b - h.sqrt();
+    }
+
+    // The cylinders of the 3 edges through the corner, along each axis.
+    for axis in 0..3 {
+        let (u, v) = ((axis + 1) % 3, (axis + 2) % 3);
+        let a = d[u] * d[u] + d[v] * d[v];
+        if a < f32::EPSILON {
+            continue;
+        }
+
+        let b = o[u] * d[u] + o[v] * d[v];
+        let c = o[u] * o[u] + o[v] * o[v] - r2;
+        let h = b * b - a * c;
+        if h > 0.0 {
+            let t = (-b - h.sqrt()) / a;
+            // The edge runs from -half to half along its axis, relative to the box.
+            if t < nearest && (o[axis] + half[axis] + d[axis] * t).abs() < half[axis] {
+                nearest = t;
+            }
+        }
+    }
+
+    (nearest.is_finite() && nearest >= 0.0).then(|| (nearest, normal_at(nearest)))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::voxel::Rgba;
+
+    use super::*;
+
+    fn floor() -> Buffer {
+        let mut buffer = Buffer::default();
+        let p = PbrProps {
+            color: Rgba::from(Color::RED),
+            ..default()
+        };
+
+        for coord in WorldCoord::iter_range((0, 0, 0).into(), (4, 0, 4).into()) {
+            buffer.set(coord, p);
+        }
+
+        buffer
+    }
+
+    fn options(max_distance: f32) -> RaycastOptions<'static> {
+        RaycastOptions {
+            max_distance,
+            ..default()
+        }
+    }
+
+    #[test]
+    fn test_sweep_aabb() {
+        let buffer = floor();
+        let half = Vec3::splat(0.5);
+
+        let hit = sweep_aabb(
+            &buffer,
+            Vec3::new(2.0, 3.0, 2.0),
+            half,
+            Vec3::NEG_Y,
+            options(10.0),
+        );
+        let hit = hit.unwrap();
+        assert_eq!(hit.time_of_impact, 1.5);
+        assert_eq!(hit.normal, Vec3::Y);
+        assert_eq!(hit.world_coord.0.y, 0);
+
+        // Sliding along the floor while resting on it.
+        let resting = Vec3::new(2.0, 1.5, 2.0);
+        assert!(sweep_aabb(&buffer, resting, half, Vec3::X, options(10.0)).is_none());
+
+        // Too short to reach the floor.
+        let hit = sweep_aabb(
+            &buffer,
+            Vec3::new(2.0, 3.0, 2.0),
+            half,
+            Vec3::NEG_Y,
+            options(1.0),
+        );
+        assert!(hit.is_none());
+    }
+
+    #[test]
+    fn test_sweep_sphere() {
+        let buffer = floor();
+
+        let hit = sweep_sphere(
+            &buffer,
+            Vec3::new(2.5, 3.0, 2.5),
+            0.5,
+            Vec3::NEG_Y,
+            options(10.0),
+        );
+        let hit = hit.unwrap();
+        assert!((hit.time_of_impact - 1.5).abs() < 1e-4);
+        assert!(hit.normal.abs_diff_eq(Vec3::Y, 1e-4));
+
+        // Clipping the top edge of the floor at x = 0, y = 1, 0.3 below the center of the sphere.
+        let hit = sweep_sphere(
+            &buffer,
+            Vec3::new(-2.0, 1.3, 2.5),
+            0.5,
+            Vec3::X,
+            options(10.0),
+        );
+        let hit = hit.unwrap();
+        assert!((hit.time_of_impact - 1.6).abs() < 1e-4);
+        assert!(hit.normal.abs_diff_eq(Vec3::new(-0.8, 0.6, 0.0), 1e-4));
+        assert_eq!(hit.world_coord, WorldCoord(IVec3::new(0, 0, 2)));
+    }
+
+    #[test]
+    fn test_sweep_unbounded() {
+        let buffer = floor();
+        let half = Vec3::splat(0.5);
+
+        // Without a max distance the sweep ends where the buffer does.
+        let hit = sweep_aabb(
+            &buffer,
+            Vec3::new(2.0, 900.0, 2.0),
+            half,
+            Vec3::NEG_Y,
+            default(),
+        );
+        assert!((hit.unwrap().time_of_impact - 898.5).abs() < 1e-3);
+        assert!(sweep_aabb(&buffer, Vec3::new(2.0, 3.0, 2.0), half, Vec3::Y, default()).is_none());
+        assert!(sweep_sphere(&Buffer::default(), Vec3::ZERO, 0.5, Vec3::X, default()).is_none());
+    }
+
+    #[test]
+    fn test_overlap() {
+        let buffer = floor();
+        let center = Vec3::new(2.0, 1.2, 2.0);
+
+        assert_eq!(overlap_aabb(&buffer, center, Vec3::splat(0.5)).len(), 4);
+        assert_eq!(overlap_sphere(&buffer, center, 0.5).len(), 4);
+        assert!(overlap_aabb(&buffer, Vec3::new(2.0, 1.5, 2.0), Vec3::splat(0.5)).is_empty());
+    }
+}